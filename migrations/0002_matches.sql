@@ -0,0 +1,38 @@
+-- Finished matches and their move lists, written when a match ends.
+
+CREATE TABLE IF NOT EXISTS matches (
+  id UUID PRIMARY KEY,
+  room_id UUID NOT NULL,
+  -- Usernames are kept even if the account is later deleted (or never existed, e.g. bots).
+  black_username TEXT NOT NULL,
+  white_username TEXT NOT NULL,
+  black_user_id UUID NULL REFERENCES users(id) ON DELETE SET NULL,
+  white_user_id UUID NULL REFERENCES users(id) ON DELETE SET NULL,
+  rule TEXT NOT NULL,
+  forbidden_policy TEXT NOT NULL,
+  opening TEXT NOT NULL,
+  board_size INT NOT NULL,
+  -- TimeControl as serialized by the server, e.g. {"kind":"fischer","mainSecs":300,...}.
+  time_control TEXT NOT NULL,
+  result TEXT NOT NULL CHECK (result IN ('black_win', 'white_win', 'draw')),
+  reason TEXT NOT NULL,
+  move_count INT NOT NULL,
+  started_at TIMESTAMPTZ NOT NULL,
+  ended_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS matches_black_user_idx
+  ON matches(black_user_id, ended_at DESC);
+
+CREATE INDEX IF NOT EXISTS matches_white_user_idx
+  ON matches(white_user_id, ended_at DESC);
+
+CREATE TABLE IF NOT EXISTS match_moves (
+  match_id UUID NOT NULL REFERENCES matches(id) ON DELETE CASCADE,
+  ply INT NOT NULL,
+  color TEXT NOT NULL CHECK (color IN ('black', 'white')),
+  row INT NOT NULL,
+  col INT NOT NULL,
+  played_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (match_id, ply)
+);
@@ -0,0 +1,25 @@
+-- Glicko-2 ratings. Only rated matches change them.
+
+ALTER TABLE users
+  ADD COLUMN IF NOT EXISTS rating DOUBLE PRECISION NOT NULL DEFAULT 1500,
+  ADD COLUMN IF NOT EXISTS rating_rd DOUBLE PRECISION NOT NULL DEFAULT 350,
+  ADD COLUMN IF NOT EXISTS rating_volatility DOUBLE PRECISION NOT NULL DEFAULT 0.06;
+
+ALTER TABLE matches
+  ADD COLUMN IF NOT EXISTS rated BOOLEAN NOT NULL DEFAULT false;
+
+-- One row per player per rated match, holding the rating after that match.
+CREATE TABLE IF NOT EXISTS rating_history (
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  match_id UUID NOT NULL REFERENCES matches(id) ON DELETE CASCADE,
+  rating DOUBLE PRECISION NOT NULL,
+  rd DOUBLE PRECISION NOT NULL,
+  volatility DOUBLE PRECISION NOT NULL,
+  -- Change in rating caused by this match.
+  delta DOUBLE PRECISION NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  PRIMARY KEY (user_id, match_id)
+);
+
+CREATE INDEX IF NOT EXISTS rating_history_user_idx
+  ON rating_history(user_id, created_at);
@@ -0,0 +1,347 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  board::{opponent, stone, Board, BLACK, EMPTY, WHITE},
+  rooms::{Color, Coord},
+  rules::{RuleSet, DIRS},
+};
+
+/// Value of a five-cell window holding `n` stones of one color and none of the other.
+const WINDOW_SCORES: [i64; 6] = [0, 1, 12, 150, 2_000, 100_000];
+
+/// Scores at or above this mean a forced win was found.
+pub const WIN_SCORE: i64 = 1_000_000_000;
+
+/// How far (in rows or columns) from an existing stone a candidate move may be.
+const CANDIDATE_RADIUS: i32 = 2;
+
+/// Strength of the built-in engine.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+  /// One ply with noisy move scores; blocks fours but misses a lot else.
+  Easy,
+  #[default]
+  Medium,
+  Hard,
+}
+
+impl Difficulty {
+  fn depth(self) -> u32 {
+    match self {
+      Difficulty::Easy => 1,
+      Difficulty::Medium => 2,
+      Difficulty::Hard => 4,
+    }
+  }
+
+  /// Moves searched at each node, best-looking first.
+  fn width(self) -> usize {
+    match self {
+      Difficulty::Easy => 8,
+      Difficulty::Medium => 10,
+      Difficulty::Hard => 12,
+    }
+  }
+
+  fn node_limit(self) -> u64 {
+    match self {
+      Difficulty::Easy => 1_000,
+      Difficulty::Medium => 20_000,
+      Difficulty::Hard => 200_000,
+    }
+  }
+
+  /// Upper bound of the random jitter added to root move scores.
+  fn noise(self) -> i64 {
+    match self {
+      Difficulty::Easy => WINDOW_SCORES[3] * 2,
+      Difficulty::Medium | Difficulty::Hard => 0,
+    }
+  }
+}
+
+fn window_value(black: usize, white: usize) -> i64 {
+  match (black, white) {
+    (0, 0) => 0,
+    (b, 0) => WINDOW_SCORES[b.min(5)],
+    (0, w) => -WINDOW_SCORES[w.min(5)],
+    _ => 0,
+  }
+}
+
+/// Change in the Black-positive evaluation if `v` were placed on the empty point (r, c).
+fn placement_delta(board: &Board, r: usize, c: usize, v: u8) -> i64 {
+  let mut delta = 0;
+  for (dr, dc) in DIRS {
+    for start in -4..=0 {
+      let (mut black, mut white) = (0, 0);
+      let mut on_board = true;
+      for k in start..start + 5 {
+        match board.get(r as i32 + dr * k, c as i32 + dc * k) {
+          None => {
+            on_board = false;
+            break;
+          }
+          Some(BLACK) => black += 1,
+          Some(WHITE) => white += 1,
+          Some(_) => {}
+        }
+      }
+      if !on_board {
+        continue;
+      }
+      let before = window_value(black, white);
+      let after = if v == BLACK {
+        window_value(black + 1, white)
+      } else {
+        window_value(black, white + 1)
+      };
+      delta += after - before;
+    }
+  }
+  delta
+}
+
+/// Static evaluation of a position, positive when Black stands better.
+pub fn evaluate(board: &Board) -> i64 {
+  let size = board.size() as i32;
+  let mut total = 0;
+  for (dr, dc) in DIRS {
+    for r in 0..size {
+      for c in 0..size {
+        let (mut black, mut white) = (0, 0);
+        let mut on_board = true;
+        for k in 0..5 {
+          match board.get(r + dr * k, c + dc * k) {
+            None => {
+              on_board = false;
+              break;
+            }
+            Some(BLACK) => black += 1,
+            Some(WHITE) => white += 1,
+            Some(_) => {}
+          }
+        }
+        if on_board {
+          total += window_value(black, white);
+        }
+      }
+    }
+  }
+  total
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+  r: usize,
+  c: usize,
+  /// Evaluation gained by the side to move playing here.
+  attack: i64,
+  /// Evaluation the opponent would gain by playing here instead.
+  defense: i64,
+}
+
+impl Candidate {
+  fn coord(&self) -> Coord {
+    Coord {
+      row: self.r as i32,
+      col: self.c as i32,
+    }
+  }
+
+  fn may_win(&self) -> bool {
+    self.attack >= WINDOW_SCORES[5] - WINDOW_SCORES[4]
+  }
+
+  fn may_lose(&self) -> bool {
+    self.defense >= WINDOW_SCORES[5] - WINDOW_SCORES[4]
+  }
+}
+
+/// Alpha-beta search over the moves near existing stones, with threat-space pruning: once the
+/// opponent threatens to make five, only the points that stop it are considered.
+struct Search {
+  board: Board,
+  rule: RuleSet,
+  /// Black-positive evaluation of `board`, kept up to date move by move.
+  eval: i64,
+  width: usize,
+  nodes: u64,
+  node_limit: u64,
+}
+
+impl Search {
+  fn new(board: &Board, rule: RuleSet, difficulty: Difficulty) -> Self {
+    Self {
+      board: board.clone(),
+      rule,
+      eval: evaluate(board),
+      width: difficulty.width(),
+      nodes: 0,
+      node_limit: difficulty.node_limit(),
+    }
+  }
+
+  fn is_legal(&self, r: usize, c: usize, v: u8) -> bool {
+    self.rule.allows(&self.board, r, c, v)
+  }
+
+  fn play(&mut self, r: usize, c: usize, v: u8) {
+    self.eval += placement_delta(&self.board, r, c, v);
+    self.board[r][c] = v;
+  }
+
+  fn unplay(&mut self, r: usize, c: usize) {
+    let v = self.board[r][c];
+    self.board[r][c] = EMPTY;
+    self.eval -= placement_delta(&self.board, r, c, v);
+  }
+
+  fn wins(&mut self, r: usize, c: usize, v: u8) -> bool {
+    self.rule.wins_at(&mut self.board, r, c, v)
+  }
+
+  /// Empty points near existing stones, most promising first.
+  fn candidates(&self, v: u8) -> Vec<Candidate> {
+    let size = self.board.size();
+    let opponent = opponent(v);
+    let sign = if v == BLACK { 1 } else { -1 };
+    let mut near = vec![false; size * size];
+    for r in 0..size {
+      for c in 0..size {
+        if self.board[r][c] == EMPTY {
+          continue;
+        }
+        for dr in -CANDIDATE_RADIUS..=CANDIDATE_RADIUS {
+          for dc in -CANDIDATE_RADIUS..=CANDIDATE_RADIUS {
+            if self.board.get(r as i32 + dr, c as i32 + dc) == Some(EMPTY) {
+              near[(r as i32 + dr) as usize * size + (c as i32 + dc) as usize] = true;
+            }
+          }
+        }
+      }
+    }
+
+    let mut out: Vec<Candidate> = (0..size * size)
+      .filter(|&i| near[i])
+      .map(|i| {
+        let (r, c) = (i / size, i % size);
+        Candidate {
+          r,
+          c,
+          attack: sign * placement_delta(&self.board, r, c, v),
+          defense: -sign * placement_delta(&self.board, r, c, opponent),
+        }
+      })
+      .collect();
+    out.sort_by_key(|m| std::cmp::Reverse(m.attack + m.defense));
+    out
+  }
+
+  /// Negamax value of the position for `v`, who is to move.
+  fn negamax(&mut self, v: u8, depth: u32, mut alpha: i64, beta: i64) -> i64 {
+    self.nodes += 1;
+    let opponent = opponent(v);
+    let cands = self.candidates(v);
+    if cands.is_empty() {
+      return 0;
+    }
+    if cands.iter().any(|m| m.may_win() && self.is_legal(m.r, m.c, v) && self.wins(m.r, m.c, v)) {
+      return WIN_SCORE + depth as i64;
+    }
+    let sign = if v == BLACK { 1 } else { -1 };
+    if depth == 0 || self.nodes >= self.node_limit {
+      return sign * self.eval;
+    }
+
+    let moves = self.moves_to_search(cands, v, opponent);
+    if moves.is_empty() {
+      // Every nearby point is forbidden for Black; treat it as a loss rather than stalling.
+      return -WIN_SCORE;
+    }
+    let mut best = -WIN_SCORE * 2;
+    for m in moves {
+      self.play(m.r, m.c, v);
+      let score = -self.negamax(opponent, depth - 1, -beta, -alpha);
+      self.unplay(m.r, m.c);
+      best = best.max(score);
+      alpha = alpha.max(score);
+      if alpha >= beta {
+        break;
+      }
+    }
+    best
+  }
+
+  /// Narrows the candidate list to the moves worth searching: the blocks when the opponent
+  /// threatens five, otherwise the best-looking legal moves.
+  fn moves_to_search(&mut self, cands: Vec<Candidate>, v: u8, opponent: u8) -> Vec<Candidate> {
+    let blocks: Vec<Candidate> = cands
+      .iter()
+      .filter(|m| m.may_lose() && self.wins(m.r, m.c, opponent))
+      .copied()
+      .collect();
+    let pool = if blocks.is_empty() { cands } else { blocks };
+    pool
+      .into_iter()
+      .filter(|m| self.is_legal(m.r, m.c, v))
+      .take(self.width)
+      .collect()
+  }
+}
+
+/// Scores the best few moves for `color` in `board`, highest first. Scores are from `color`'s
+/// point of view; anything above `WIN_SCORE` is a forced win found within the search depth.
+pub fn rank_moves(board: &Board, color: Color, rule: RuleSet, difficulty: Difficulty) -> Vec<(Coord, i64)> {
+  if is_empty(board) {
+    let mid = (board.size() / 2) as i32;
+    return vec![(Coord { row: mid, col: mid }, 0)];
+  }
+  let v = stone(color);
+  let opponent = stone(color.other());
+  let mut search = Search::new(board, rule, difficulty);
+
+  let cands = search.candidates(v);
+  if let Some(win) = cands
+    .iter()
+    .find(|m| m.may_win() && search.is_legal(m.r, m.c, v) && search.wins(m.r, m.c, v))
+  {
+    return vec![(win.coord(), WIN_SCORE)];
+  }
+
+  let depth = difficulty.depth();
+  let noise = difficulty.noise();
+  let mut rng = rand::thread_rng();
+  let mut ranked = vec![];
+  for m in search.moves_to_search(cands, v, opponent) {
+    search.play(m.r, m.c, v);
+    let mut score = -search.negamax(opponent, depth - 1, -WIN_SCORE * 2, WIN_SCORE * 2);
+    search.unplay(m.r, m.c);
+    if noise > 0 {
+      score += rng.gen_range(0..=noise);
+    }
+    ranked.push((m.coord(), score));
+  }
+  if ranked.is_empty() {
+    // Nothing playable near the stones (e.g. all forbidden for Black): take any legal point.
+    let size = board.size();
+    let any = (0..size * size)
+      .map(|i| (i / size, i % size))
+      .find(|&(r, c)| board[r][c] == EMPTY && search.is_legal(r, c, v));
+    ranked.extend(any.map(|(r, c)| (Coord { row: r as i32, col: c as i32 }, 0)));
+  }
+  ranked.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+  ranked
+}
+
+fn is_empty(board: &Board) -> bool {
+  let size = board.size();
+  (0..size).all(|r| board[r].iter().all(|&v| v == EMPTY))
+}
+
+/// The engine's move for `color`, or `None` if there is nowhere left to play.
+pub fn choose_move(board: &Board, color: Color, rule: RuleSet, difficulty: Difficulty) -> Option<Coord> {
+  rank_moves(board, color, rule, difficulty).into_iter().next().map(|(coord, _)| coord)
+}
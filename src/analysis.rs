@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{
+  ai::{self, Difficulty},
+  board::{stone, Board, EMPTY, MAX_BOARD_SIZE, MIN_BOARD_SIZE, WHITE},
+  error::ApiError,
+  rooms::{Color, Coord},
+  rules::{RuleSet, DIRS},
+  solver::{self, Kind, Limits, Verdict},
+};
+
+pub const DEFAULT_TOP: usize = 5;
+pub const MAX_TOP: usize = 10;
+
+/// Candidates shown for a `match.hint`.
+pub const HINT_TOP: usize = 3;
+
+/// Solver budget per side and kind, small enough for a hint on a busy server.
+const SOLVER_LIMITS: Limits = Limits {
+  max_depth: 8,
+  max_nodes: 5_000,
+  max_time: Duration::from_millis(250),
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+  pub coord: Coord,
+  /// Engine score from the mover's point of view; higher is better.
+  pub score: i64,
+  /// The search found a forced win starting with this move.
+  pub winning: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ThreatKind {
+  /// Playing here makes five: the side already has a four.
+  Four,
+  /// Playing here makes an open four: the side has an open three.
+  OpenThree,
+  /// Playing here starts a win by continuous fours.
+  Vcf,
+  /// Playing here starts a win by continuous fours and threes.
+  Vct,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Threat {
+  pub color: Color,
+  pub kind: ThreatKind,
+  /// Point that completes the threat.
+  pub coord: Coord,
+  /// The whole winning sequence, for `vcf` and `vct`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub line: Option<Vec<Coord>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Analysis {
+  #[serde(rename = "toMove")]
+  pub to_move: Color,
+  pub candidates: Vec<Candidate>,
+  pub threats: Vec<Threat>,
+}
+
+/// Replays `moves` (Black first, alternating) onto an empty board. Returns the board and the side
+/// to move, or `BadRequest` for off-board or repeated points.
+pub fn replay(board_size: usize, moves: &[Coord]) -> Result<(Board, Color), ApiError> {
+  if !(MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&board_size) {
+    return Err(ApiError::BadRequest);
+  }
+  let mut board = Board::new(board_size);
+  let mut color = Color::Black;
+  for mv in moves {
+    if board.get(mv.row, mv.col) != Some(EMPTY) {
+      return Err(ApiError::BadRequest);
+    }
+    board[mv.row as usize][mv.col as usize] = stone(color);
+    color = color.other();
+  }
+  Ok((board, color))
+}
+
+/// Builds a board from rows of cells in the usual encoding (0 empty, 1 Black, 2 White).
+pub fn from_cells(rows: &[Vec<u8>]) -> Result<Board, ApiError> {
+  let size = rows.len();
+  if !(MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&size) {
+    return Err(ApiError::BadRequest);
+  }
+  let mut board = Board::new(size);
+  for (r, row) in rows.iter().enumerate() {
+    if row.len() != size || row.iter().any(|&v| v > WHITE) {
+      return Err(ApiError::BadRequest);
+    }
+    board[r].copy_from_slice(row);
+  }
+  Ok(board)
+}
+
+/// Any completion point of a four or three sits right next to one of the side's stones.
+fn touches(board: &Board, r: usize, c: usize, v: u8) -> bool {
+  (-1..=1).any(|dr| (-1..=1).any(|dc| board.get(r as i32 + dr, c as i32 + dc) == Some(v)))
+}
+
+/// Whether `v` on the empty point (r, c) makes an open four.
+fn makes_open_four(board: &mut Board, rule: RuleSet, r: usize, c: usize, v: u8) -> bool {
+  board[r][c] = v;
+  let open = DIRS.iter().any(|&dir| rule.open_four_along(board, r, c, dir, v));
+  board[r][c] = EMPTY;
+  open
+}
+
+/// Fours and open threes on the board for both sides, located by the points that complete them.
+/// Renju forbidden points don't count for Black.
+pub fn threats(board: &Board, rule: RuleSet) -> Vec<Threat> {
+  let mut scratch = board.clone();
+  let size = board.size();
+  let mut out = vec![];
+  for color in [Color::Black, Color::White] {
+    let v = stone(color);
+    for r in 0..size {
+      for c in 0..size {
+        if board[r][c] != EMPTY || !touches(board, r, c, v) {
+          continue;
+        }
+        let kind = if rule.wins_legally_at(&mut scratch, r, c, v) {
+          ThreatKind::Four
+        } else if makes_open_four(&mut scratch, rule, r, c, v) && rule.allows(board, r, c, v) {
+          ThreatKind::OpenThree
+        } else {
+          continue;
+        };
+        out.push(Threat {
+          color,
+          kind,
+          coord: Coord {
+            row: r as i32,
+            col: c as i32,
+          },
+          line: None,
+        });
+      }
+    }
+  }
+  out
+}
+
+/// Forced wins for either side, as if it were that side's move: VCF where there is one, else VCT.
+fn forced_wins(board: &Board, rule: RuleSet) -> Vec<Threat> {
+  let mut out = vec![];
+  for color in [Color::Black, Color::White] {
+    let win = [Kind::Vcf, Kind::Vct].into_iter().find_map(|kind| {
+      let s = solver::solve(board, color, rule, kind, SOLVER_LIMITS);
+      // A single move is a plain five, already reported as a four.
+      (s.verdict == Verdict::Win && s.line.len() > 1).then_some((kind, s.line))
+    });
+    if let Some((kind, line)) = win {
+      out.push(Threat {
+        color,
+        kind: match kind {
+          Kind::Vcf => ThreatKind::Vcf,
+          Kind::Vct => ThreatKind::Vct,
+        },
+        coord: line[0].clone(),
+        line: Some(line),
+      });
+    }
+  }
+  out
+}
+
+/// The engine's `top` best moves for `to_move` plus the threats on the board. A forced win the
+/// solver finds for `to_move` is put first even when the engine's shorter search missed it.
+/// CPU-bound; run it off the async executor.
+pub fn analyze(board: &Board, to_move: Color, rule: RuleSet, top: usize) -> Analysis {
+  let mut candidates: Vec<Candidate> = ai::rank_moves(board, to_move, rule, Difficulty::Hard)
+    .into_iter()
+    .map(|(coord, score)| Candidate {
+      coord,
+      score,
+      winning: score >= ai::WIN_SCORE,
+    })
+    .collect();
+  let mut threats = threats(board, rule);
+  threats.extend(forced_wins(board, rule));
+
+  let solved = threats
+    .iter()
+    .find(|t| t.color == to_move && t.line.is_some())
+    .map(|t| t.coord.clone());
+  if let Some(coord) = solved
+    && !candidates.first().is_some_and(|c| c.winning)
+  {
+    candidates.retain(|c| (c.coord.row, c.coord.col) != (coord.row, coord.col));
+    candidates.insert(
+      0,
+      Candidate {
+        coord,
+        score: ai::WIN_SCORE,
+        winning: true,
+      },
+    );
+  }
+  candidates.truncate(top);
+  Analysis {
+    to_move,
+    candidates,
+    threats,
+  }
+}
@@ -1,17 +1,25 @@
 use axum::{
-  extract::{FromRef, State},
+  extract::{FromRef, Path, Query, State},
   routing::{get, post},
   Json, Router,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::{
-  auth,
+  analysis, auth,
+  board::{Board, DEFAULT_BOARD_SIZE},
   config::Config,
   error::{ApiError, ApiResult},
-  rooms,
-  ws,
+  history::{self, MatchDetail, MatchFilter, MatchPage, PlayerResult},
+  leaderboard::{self, BoardSpec, Leaderboard, Metric, Period},
+  matchmaking::Matchmaker,
+  ratings::{self, Rating},
+  rooms::{self, Color, Coord},
+  rules::RuleSet,
+  solver, ws,
 };
 
 #[derive(Clone)]
@@ -20,6 +28,7 @@ pub struct AppState {
   pub pool: PgPool,
   pub hub: ws::Hub,
   pub rooms: rooms::RoomService,
+  pub matchmaker: Matchmaker,
 }
 
 impl FromRef<AppState> for Config {
@@ -46,6 +55,12 @@ impl FromRef<AppState> for rooms::RoomService {
   }
 }
 
+impl FromRef<AppState> for Matchmaker {
+  fn from_ref(state: &AppState) -> Self {
+    state.matchmaker.clone()
+  }
+}
+
 pub fn router(state: AppState) -> Router {
   Router::new()
       .nest(
@@ -57,6 +72,12 @@ pub fn router(state: AppState) -> Router {
             .route("/me", get(me))
             .route("/logout", post(logout)),
       )
+      .route("/api/v1/users/{username}/matches", get(user_matches))
+      .route("/api/v1/matches/{match_id}", get(match_detail))
+      .route("/api/v1/leaderboard", get(leaderboard_page))
+      .route("/api/v1/rooms", get(list_rooms))
+      .route("/api/v1/analysis", post(analyze_position))
+      .route("/api/v1/analysis/solve", post(solve_position))
       .route("/ws", get(ws::ws_handler))
       .with_state(state)
 }
@@ -161,23 +182,33 @@ async fn refresh(
 #[derive(Debug, Serialize)]
 struct MeResp {
   username: String,
+  rating: Rating,
+}
+
+/// Claims of the `Authorization: Bearer ...` token, or `None` when the header is absent.
+fn bearer_claims(cfg: &Config, headers: &axum::http::HeaderMap) -> ApiResult<Option<auth::Claims>> {
+  let Some(authz) = headers.get(axum::http::header::AUTHORIZATION) else {
+    return Ok(None);
+  };
+  let token = authz
+      .to_str()
+      .ok()
+      .and_then(|v| v.strip_prefix("Bearer "))
+      .ok_or(ApiError::Unauthorized)?;
+  auth::verify_access_token(cfg, token).map(Some)
 }
 
 async fn me(
   State(cfg): State<Config>,
+  State(pool): State<PgPool>,
   headers: axum::http::HeaderMap,
 ) -> ApiResult<Json<MeResp>> {
-  let authz = headers
-      .get(axum::http::header::AUTHORIZATION)
-      .and_then(|v| v.to_str().ok())
-      .ok_or(ApiError::Unauthorized)?;
-
-  let token = authz
-      .strip_prefix("Bearer ")
-      .ok_or(ApiError::Unauthorized)?;
-
-  let claims = auth::verify_access_token(&cfg, token)?;
-  Ok(Json(MeResp { username: claims.sub }))
+  let claims = bearer_claims(&cfg, &headers)?.ok_or(ApiError::Unauthorized)?;
+  let rating = ratings::load(&pool, &claims.sub).await?.ok_or(ApiError::Unauthorized)?;
+  Ok(Json(MeResp {
+    username: claims.sub,
+    rating,
+  }))
 }
 
 #[derive(Debug, Deserialize)]
@@ -198,3 +229,171 @@ async fn logout(
   auth::logout(&pool, &req.refresh_token).await?;
   Ok(Json(LogoutResp { ok: true }))
 }
+
+#[derive(Debug, Deserialize)]
+struct MatchListQuery {
+  result: Option<PlayerResult>,
+  opponent: Option<String>,
+  from: Option<DateTime<Utc>>,
+  to: Option<DateTime<Utc>>,
+  cursor: Option<String>,
+  limit: Option<i64>,
+}
+
+async fn user_matches(
+  State(pool): State<PgPool>,
+  Path(username): Path<String>,
+  Query(q): Query<MatchListQuery>,
+) -> ApiResult<Json<MatchPage>> {
+  let filter = MatchFilter {
+    result: q.result,
+    opponent: q.opponent.filter(|o| !o.trim().is_empty()),
+    from: q.from,
+    to: q.to,
+  };
+  let page = history::list_user_matches(
+    &pool,
+    username.trim(),
+    &filter,
+    q.cursor.as_deref(),
+    q.limit.unwrap_or(history::DEFAULT_PAGE_SIZE),
+  )
+  .await?;
+  Ok(Json(page))
+}
+
+async fn match_detail(
+  State(pool): State<PgPool>,
+  Path(match_id): Path<String>,
+) -> ApiResult<Json<MatchDetail>> {
+  let match_id = Uuid::parse_str(&match_id).map_err(|_| ApiError::NotFound)?;
+  Ok(Json(history::get_match(&pool, match_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct LeaderboardQuery {
+  #[serde(default)]
+  period: Period,
+  #[serde(default)]
+  by: Metric,
+  offset: Option<i64>,
+  limit: Option<i64>,
+}
+
+async fn leaderboard_page(
+  State(cfg): State<Config>,
+  State(pool): State<PgPool>,
+  Query(q): Query<LeaderboardQuery>,
+  headers: axum::http::HeaderMap,
+) -> ApiResult<Json<Leaderboard>> {
+  let caller = bearer_claims(&cfg, &headers)?.map(|c| c.sub);
+  let spec = BoardSpec {
+    period: q.period,
+    metric: q.by,
+    ties: cfg.leaderboard_ties,
+    min_games: cfg.leaderboard_min_games,
+  };
+  let board = leaderboard::load(
+    &pool,
+    spec,
+    q.offset.unwrap_or(0),
+    q.limit.unwrap_or(leaderboard::DEFAULT_PAGE_SIZE),
+    caller.as_deref(),
+  )
+  .await?;
+  Ok(Json(board))
+}
+
+#[derive(Debug, Serialize)]
+struct RoomListResp {
+  rooms: Vec<rooms::LobbyRoom>,
+}
+
+async fn list_rooms(State(rooms): State<rooms::RoomService>) -> Json<RoomListResp> {
+  Json(RoomListResp {
+    rooms: rooms.lobby_list().await,
+  })
+}
+
+/// A position given either as a move list or as a board plus side to move.
+#[derive(Debug, Deserialize)]
+struct PositionReq {
+  #[serde(rename = "boardSize")]
+  board_size: Option<usize>,
+  #[serde(default)]
+  rule: RuleSet,
+  /// Moves in order, Black first. Alternatively give `board` and `toMove`.
+  #[serde(default)]
+  moves: Vec<Coord>,
+  /// Rows of cells: 0 empty, 1 Black, 2 White.
+  board: Option<Vec<Vec<u8>>>,
+  #[serde(rename = "toMove")]
+  to_move: Option<Color>,
+}
+
+impl PositionReq {
+  fn position(&self) -> ApiResult<(Board, Color)> {
+    match &self.board {
+      Some(rows) => Ok((analysis::from_cells(rows)?, self.to_move.ok_or(ApiError::BadRequest)?)),
+      None => {
+        let (board, next) =
+          analysis::replay(self.board_size.unwrap_or(DEFAULT_BOARD_SIZE), &self.moves)?;
+        Ok((board, self.to_move.unwrap_or(next)))
+      }
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct AnalysisReq {
+  #[serde(flatten)]
+  position: PositionReq,
+  top: Option<usize>,
+}
+
+async fn analyze_position(
+  State(cfg): State<Config>,
+  headers: axum::http::HeaderMap,
+  Json(req): Json<AnalysisReq>,
+) -> ApiResult<Json<analysis::Analysis>> {
+  bearer_claims(&cfg, &headers)?.ok_or(ApiError::Unauthorized)?;
+  let (board, to_move) = req.position.position()?;
+  let top = req.top.unwrap_or(analysis::DEFAULT_TOP).clamp(1, analysis::MAX_TOP);
+  let rule = req.position.rule;
+  let result = tokio::task::spawn_blocking(move || analysis::analyze(&board, to_move, rule, top))
+    .await
+    .map_err(|_| ApiError::Internal)?;
+  Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize)]
+struct SolveReq {
+  #[serde(flatten)]
+  position: PositionReq,
+  #[serde(default)]
+  kind: solver::Kind,
+  #[serde(rename = "maxDepth")]
+  max_depth: Option<u32>,
+  #[serde(rename = "maxNodes")]
+  max_nodes: Option<u64>,
+}
+
+async fn solve_position(
+  State(cfg): State<Config>,
+  headers: axum::http::HeaderMap,
+  Json(req): Json<SolveReq>,
+) -> ApiResult<Json<solver::Solution>> {
+  bearer_claims(&cfg, &headers)?.ok_or(ApiError::Unauthorized)?;
+  let (board, to_move) = req.position.position()?;
+  let defaults = solver::Limits::for_kind(req.kind);
+  let limits = solver::Limits {
+    max_depth: req.max_depth.unwrap_or(defaults.max_depth).min(solver::MAX_DEPTH),
+    max_nodes: req.max_nodes.unwrap_or(defaults.max_nodes).min(solver::REQUEST_MAX_NODES),
+    max_time: defaults.max_time.min(solver::REQUEST_MAX_TIME),
+  };
+  let (rule, kind) = (req.position.rule, req.kind);
+  let result = tokio::task::spawn_blocking(move || solver::solve(&board, to_move, rule, kind, limits))
+    .await
+    .map_err(|_| ApiError::Internal)?;
+  Ok(Json(result))
+}
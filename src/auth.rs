@@ -13,7 +13,7 @@ use sha2::{Digest, Sha256};
 use sqlx::{PgPool, Row};
 use uuid::Uuid;
 
-use crate::{config::Config, error::ApiError};
+use crate::{bots, config::Config, error::ApiError};
 
 #[derive(Debug, Clone)]
 pub struct Tokens {
@@ -101,7 +101,8 @@ pub fn verify_access_token(cfg: &Config, token: &str) -> Result<Claims, ApiError
 }
 
 pub async fn create_user(pool: &PgPool, username: &str, password: &str) -> Result<(), ApiError> {
-  if username.is_empty() || password.len() < 6 {
+  // The bot prefix is reserved so nobody can impersonate a server-side engine.
+  if username.is_empty() || bots::is_bot(username) || password.len() < 6 {
     return Err(ApiError::BadRequest);
   }
 
@@ -123,10 +124,10 @@ pub async fn create_user(pool: &PgPool, username: &str, password: &str) -> Resul
   match res {
     Ok(_) => Ok(()),
     Err(e) => {
-      if let Some(db_err) = e.as_database_error() {
-        if db_err.code().as_deref() == Some("23505") {
-          return Err(ApiError::UsernameTaken);
-        }
+      if let Some(db_err) = e.as_database_error()
+        && db_err.code().as_deref() == Some("23505")
+      {
+        return Err(ApiError::UsernameTaken);
       }
       Err(ApiError::Internal)
     }
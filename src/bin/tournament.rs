@@ -0,0 +1,144 @@
+//! Headless engine-vs-engine tournaments on the server's game logic.
+//!
+//! ```text
+//! tournament [options] ENGINE ENGINE...
+//!
+//! ENGINE is `builtin:easy|medium|hard` or `name=/path/to/pbrain-engine` (Gomocup protocol).
+//!
+//! --format round-robin|gauntlet   default round-robin; in a gauntlet the first engine plays the rest
+//! --games N                       games per pairing, colors alternating (default 2)
+//! --rule freestyle|standard|caro|renju
+//! --opening free|swap2|pro|longPro
+//! --board-size N                  default 15
+//! --timeout-ms MS                 per-move limit for external engines (default 5000)
+//! --out FILE                      where to write the games (default tournament.pgn)
+//! ```
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Context, bail};
+use server::{
+  ai::Difficulty,
+  bots::{Builtin, Engine},
+  piskvork::{EngineSpec, PiskvorkEngine},
+  rooms::{RoomOptions, RoomService},
+  tournament::{self, Crosstable, Entrant, Format},
+};
+use tracing_subscriber::EnvFilter;
+
+struct Args {
+  format: Format,
+  games: u32,
+  options: RoomOptions,
+  timeout: Duration,
+  out: String,
+  engines: Vec<String>,
+}
+
+fn parse_enum<T: serde::de::DeserializeOwned>(flag: &str, value: &str) -> anyhow::Result<T> {
+  serde_json::from_value(serde_json::Value::String(value.to_string()))
+    .with_context(|| format!("invalid {flag} {value:?}"))
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+  let mut args = Args {
+    format: Format::RoundRobin,
+    games: 2,
+    options: RoomOptions::default(),
+    timeout: Duration::from_millis(5000),
+    out: "tournament.pgn".to_string(),
+    engines: vec![],
+  };
+  let mut it = std::env::args().skip(1);
+  while let Some(arg) = it.next() {
+    if !arg.starts_with("--") {
+      args.engines.push(arg);
+      continue;
+    }
+    let value = it.next().with_context(|| format!("missing value for {arg}"))?;
+    match arg.as_str() {
+      "--format" => {
+        args.format = value.parse().ok().with_context(|| format!("invalid --format {value:?}"))?
+      }
+      "--games" => args.games = value.parse().context("invalid --games")?,
+      "--rule" => args.options.rule = parse_enum(&arg, &value)?,
+      "--opening" => args.options.opening = parse_enum(&arg, &value)?,
+      "--board-size" => args.options.board_size = value.parse().context("invalid --board-size")?,
+      "--timeout-ms" => args.timeout = Duration::from_millis(value.parse().context("invalid --timeout-ms")?),
+      "--out" => args.out = value,
+      _ => bail!("unknown option {arg}"),
+    }
+  }
+  if args.engines.len() < 2 {
+    bail!("need at least two engines (builtin:<level> or name=path)");
+  }
+  args.options.validate().map_err(|e| anyhow::anyhow!("invalid game options: {e}"))?;
+  Ok(args)
+}
+
+fn entrant(arg: &str, timeout: Duration) -> anyhow::Result<Entrant> {
+  if let Some(level) = arg.strip_prefix("builtin:") {
+    let difficulty: Difficulty = parse_enum("builtin level", level)?;
+    return Ok(Entrant {
+      name: format!("builtin-{level}"),
+      engine: Box::new(Builtin(difficulty)),
+    });
+  }
+  let spec = EngineSpec::parse_list(arg, timeout)?
+    .pop()
+    .with_context(|| format!("invalid engine {arg:?}"))?;
+  Ok(Entrant {
+    name: spec.name.clone(),
+    engine: Box::new(PiskvorkEngine::new(spec)) as Box<dyn Engine>,
+  })
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  tracing_subscriber::fmt()
+      .with_env_filter(EnvFilter::from_default_env().add_directive("warn".parse()?))
+      .init();
+
+  let args = parse_args()?;
+  let mut entrants = args
+    .engines
+    .iter()
+    .map(|e| entrant(e, args.timeout))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+  let index: HashMap<String, usize> = entrants.iter().enumerate().map(|(i, e)| (e.name.clone(), i)).collect();
+  if index.len() != entrants.len() {
+    bail!("engine names must be unique");
+  }
+
+  let (sink, mut finished) = tokio::sync::mpsc::unbounded_channel();
+  let rooms = RoomService::with_history(sink);
+  let mut table = Crosstable::new(entrants.iter().map(|e| e.name.clone()).collect());
+  let mut notation = String::new();
+
+  let games = tournament::schedule(args.format, entrants.len(), args.games);
+  for (round, pairing) in games.iter().enumerate() {
+    let [black, white] = entrants
+      .get_disjoint_mut([pairing.black, pairing.white])
+      .context("engine paired with itself")?;
+    let game = tournament::play_game(&rooms, &mut finished, &args.options, black, white).await?;
+    println!(
+      "game {}/{}: {} - {}: {} ({})",
+      round + 1,
+      games.len(),
+      game.black,
+      game.white,
+      game.result(),
+      game.reason
+    );
+    table.record(index[&game.black], index[&game.white], game.winner);
+    notation.push_str(&tournament::game_notation(round + 1, &game));
+  }
+
+  for e in &mut entrants {
+    e.engine.shutdown().await;
+  }
+  std::fs::write(&args.out, notation).with_context(|| format!("failed to write {}", args.out))?;
+  println!("\n{table}");
+  println!("games written to {}", args.out);
+  Ok(())
+}
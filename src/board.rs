@@ -0,0 +1,72 @@
+use std::ops::{Index, IndexMut};
+
+use crate::rooms::Color;
+
+pub const DEFAULT_BOARD_SIZE: usize = 15;
+pub const MIN_BOARD_SIZE: usize = 9;
+pub const MAX_BOARD_SIZE: usize = 25;
+
+pub const EMPTY: u8 = 0;
+pub const BLACK: u8 = 1;
+pub const WHITE: u8 = 2;
+
+/// Cell value of `color`'s stones.
+pub(crate) fn stone(color: Color) -> u8 {
+  match color {
+    Color::Black => BLACK,
+    Color::White => WHITE,
+  }
+}
+
+/// The other side's stone value.
+pub(crate) fn opponent(v: u8) -> u8 {
+  if v == BLACK { WHITE } else { BLACK }
+}
+
+/// Square board of stones stored row-major. `board[r][c]` indexes like the old fixed-size array.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Board {
+  size: usize,
+  cells: Vec<u8>,
+}
+
+impl Default for Board {
+  fn default() -> Self {
+    Self::new(DEFAULT_BOARD_SIZE)
+  }
+}
+
+impl Board {
+  pub fn new(size: usize) -> Self {
+    Self {
+      size,
+      cells: vec![EMPTY; size * size],
+    }
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// Stone at (r, c), or `None` when the point is off the board.
+  pub fn get(&self, r: i32, c: i32) -> Option<u8> {
+    if r < 0 || c < 0 || r as usize >= self.size || c as usize >= self.size {
+      return None;
+    }
+    Some(self.cells[r as usize * self.size + c as usize])
+  }
+}
+
+impl Index<usize> for Board {
+  type Output = [u8];
+
+  fn index(&self, row: usize) -> &[u8] {
+    &self.cells[row * self.size..(row + 1) * self.size]
+  }
+}
+
+impl IndexMut<usize> for Board {
+  fn index_mut(&mut self, row: usize) -> &mut [u8] {
+    &mut self.cells[row * self.size..(row + 1) * self.size]
+  }
+}
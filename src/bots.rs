@@ -0,0 +1,302 @@
+use anyhow::anyhow;
+use axum::extract::ws::Message;
+use futures_util::future::BoxFuture;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+  ai::{self, Difficulty},
+  board::{stone, Board, EMPTY},
+  opening::{OpeningChoice, OpeningPhase, OpeningRule},
+  protocol::EnvelopeOut,
+  rooms::{Color, Coord, MatchSnapshot, Move, RoomService, RoomSnapshot, RoomState, SeatInfo},
+  rules::RuleSet,
+  ws::{self, Hub},
+};
+
+/// Usernames starting with this belong to server-side engines, never to registered users.
+pub const BOT_PREFIX: &str = "bot:";
+
+/// Evaluation margin (in `ai::evaluate` units) above which a side is worth picking in Swap2.
+const SWAP_MARGIN: i64 = 20;
+
+pub fn is_bot(username: &str) -> bool {
+  username.starts_with(BOT_PREFIX)
+}
+
+/// A fresh, unique bot username, e.g. `bot:hard-3f9a1c` for `label` "hard".
+pub fn bot_username(label: &str) -> String {
+  let id = Uuid::new_v4().simple().to_string();
+  format!("{BOT_PREFIX}{label}-{}", &id[..6])
+}
+
+/// Position an engine is asked to move in.
+#[derive(Debug, Clone)]
+pub struct Position {
+  /// Match the position belongs to; engines that keep state start over when it changes.
+  pub match_id: String,
+  pub board_size: usize,
+  pub rule: RuleSet,
+  pub opening: OpeningRule,
+  pub moves: Vec<Move>,
+  /// Color of the stone to place next.
+  pub to_move: Color,
+  /// What is left on the mover's clock, for timed matches.
+  pub time_left_ms: Option<u64>,
+}
+
+impl Position {
+  pub fn from_match(m: &MatchSnapshot) -> Self {
+    let time_left_ms = m.clocks.map(|c| match m.turn {
+      Color::Black => c.black.remaining_ms,
+      Color::White => c.white.remaining_ms,
+    });
+    Self {
+      match_id: m.match_id.clone(),
+      board_size: m.board_size,
+      rule: m.rule,
+      opening: m.opening,
+      moves: m.moves.clone(),
+      to_move: m.turn,
+      time_left_ms,
+    }
+  }
+
+  pub fn board(&self) -> Board {
+    let mut board = Board::new(self.board_size);
+    for mv in &self.moves {
+      board[mv.coord.row as usize][mv.coord.col as usize] = stone(mv.color);
+    }
+    board
+  }
+}
+
+/// Whatever decides the moves for a bot seat.
+pub trait Engine: Send + Sync {
+  fn think<'a>(&'a mut self, position: &'a Position) -> BoxFuture<'a, anyhow::Result<Coord>>;
+
+  /// Releases the engine once its bot is done playing.
+  fn shutdown(&mut self) -> BoxFuture<'_, ()> {
+    Box::pin(async {})
+  }
+}
+
+/// The in-process search from [`ai`].
+pub struct Builtin(pub Difficulty);
+
+impl Engine for Builtin {
+  fn think<'a>(&'a mut self, position: &'a Position) -> BoxFuture<'a, anyhow::Result<Coord>> {
+    let position = position.clone();
+    let difficulty = self.0;
+    Box::pin(async move {
+      tokio::task::spawn_blocking(move || builtin_move(&position, difficulty))
+        .await?
+        .ok_or_else(|| anyhow!("no legal move left"))
+    })
+  }
+}
+
+/// The search's favourite move that the opening rule allows. The search itself knows nothing
+/// about Pro-style restrictions, so when all its picks are ruled out the allowed point nearest the
+/// center is played instead.
+fn builtin_move(position: &Position, difficulty: Difficulty) -> Option<Coord> {
+  let board = position.board();
+  let (size, index) = (position.board_size, position.moves.len());
+  let ranked = ai::rank_moves(&board, position.to_move, position.rule, difficulty);
+  if let Some((coord, _)) = ranked.into_iter().find(|(c, _)| position.opening.permits(size, index, c)) {
+    return Some(coord);
+  }
+  let mid = (size / 2) as i32;
+  (0..size * size)
+    .map(|i| Coord {
+      row: (i / size) as i32,
+      col: (i % size) as i32,
+    })
+    .filter(|c| board[c.row as usize][c.col as usize] == EMPTY && position.opening.permits(size, index, c))
+    .filter(|c| position.rule.allows(&board, c.row as usize, c.col as usize, stone(position.to_move)))
+    .min_by_key(|c| (c.row - mid).abs().max((c.col - mid).abs()))
+}
+
+/// Connects the bot `username` to the hub and starts the task that plays for it. The bot sees
+/// the same room events a player's socket would and acts on them through `RoomService`.
+pub fn spawn(hub: Hub, rooms: RoomService, username: String, engine: Box<dyn Engine>) {
+  let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+  hub.register(username.clone(), tx.clone());
+
+  tokio::spawn(async move {
+    let mut bot = Bot {
+      hub: &hub,
+      rooms: &rooms,
+      username: &username,
+      engine,
+    };
+    // Nothing has been broadcast yet, so take the first look unprompted.
+    let mut active = bot.act().await;
+    while active {
+      let Some(msg) = rx.recv().await else { break };
+      match msg {
+        Message::Close(_) => break,
+        // Every room event is a cue to look at the room again; the content doesn't matter.
+        Message::Text(_) => active = bot.act().await,
+        _ => {}
+      }
+    }
+    bot.engine.shutdown().await;
+    hub.unregister(&username, &tx);
+    tracing::info!(bot = %username, "bot stopped");
+  });
+}
+
+struct Bot<'a> {
+  hub: &'a Hub,
+  rooms: &'a RoomService,
+  username: &'a str,
+  engine: Box<dyn Engine>,
+}
+
+impl Bot<'_> {
+  /// Does whatever the room currently expects of the bot. Returns `false` once the bot is no
+  /// longer in a room and should stop.
+  async fn act(&mut self) -> bool {
+    let Some(room_id) = self.rooms.room_id_for_user(self.username) else {
+      return false;
+    };
+    let Some(snapshot) = self.rooms.snapshot(room_id).await else {
+      return false;
+    };
+    if !has_humans(&snapshot) {
+      ws::leave_room_with_broadcast(self.hub, self.rooms, room_id, self.username).await;
+      return false;
+    }
+    let Some(color) = seat_color(&snapshot, self.username) else {
+      return true;
+    };
+
+    match snapshot.state {
+      RoomState::Waiting => {
+        if snapshot.rematch_requested_by.as_deref().is_some_and(|u| u != self.username) {
+          let res = self.rooms.match_rematch(self.username).await;
+          self.publish(res).await;
+          return true;
+        }
+        let seat = match color {
+          Color::Black => &snapshot.seats.black,
+          Color::White => &snapshot.seats.white,
+        };
+        if seat.as_ref().is_some_and(|s| !s.ready) {
+          self.ready().await;
+        }
+      }
+      RoomState::Playing => self.play(&snapshot, color).await,
+    }
+    true
+  }
+
+  async fn ready(&self) {
+    let Ok((room_id, snapshot, start)) = self.rooms.set_ready(self.username, true).await else {
+      return;
+    };
+    let snap_evt = EnvelopeOut::event("room.snapshot", serde_json::to_value(snapshot).unwrap());
+    let mut events = vec![snap_evt];
+    events.extend(start);
+    self.broadcast(room_id, &events).await;
+  }
+
+  async fn play(&mut self, snapshot: &RoomSnapshot, color: Color) {
+    let Some(m) = &snapshot.current_match else {
+      return;
+    };
+    // Takebacks are always granted; draws are always played out.
+    if m.undo_request == Some(color.other()) {
+      let res = self.rooms.match_respond_undo(self.username, true).await;
+      self.publish(res).await;
+      return;
+    }
+    if m.draw_offer == Some(color.other()) {
+      let res = self.rooms.match_respond_draw(self.username, false).await;
+      self.publish(res).await;
+      return;
+    }
+
+    let actor = m.opening_phase.map(OpeningPhase::actor).unwrap_or(m.turn);
+    if actor != color {
+      return;
+    }
+    let position = Position::from_match(m);
+
+    if m.opening_phase.is_some_and(OpeningPhase::is_choice) {
+      let choice = swap_choice(&position.board(), m.opening_phase);
+      let res = self.rooms.match_opening_choice(self.username, choice).await;
+      self.publish(res).await;
+      return;
+    }
+
+    match self.engine.think(&position).await {
+      Ok(coord) => self.play_move(coord).await,
+      Err(e) => {
+        tracing::warn!(bot = %self.username, error = %e, "engine failed, resigning");
+        self.resign().await;
+      }
+    }
+  }
+
+  async fn play_move(&self, coord: Coord) {
+    let res = self.rooms.match_move(self.username, coord.clone()).await;
+    let rejected = match &res {
+      Ok((_, payload, _)) => payload.get("accepted").and_then(|v| v.as_bool()) == Some(false),
+      Err(_) => false,
+    };
+    self.publish(res).await;
+    if rejected {
+      // An illegal reply would otherwise leave the bot waiting forever on its own turn.
+      tracing::warn!(bot = %self.username, ?coord, "bot move rejected, resigning");
+      self.resign().await;
+    }
+  }
+
+  async fn resign(&self) {
+    let res = self.rooms.match_resign(self.username).await;
+    self.publish(res).await;
+  }
+
+  async fn publish(&self, res: Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)>) {
+    if let Ok((room_id, _, events)) = res {
+      self.broadcast(room_id, &events).await;
+    }
+  }
+
+  async fn broadcast(&self, room_id: Uuid, events: &[EnvelopeOut]) {
+    for evt in events {
+      ws::broadcast_room_event(self.hub, self.rooms, room_id, evt).await;
+    }
+  }
+}
+
+fn seat_color(snapshot: &RoomSnapshot, username: &str) -> Option<Color> {
+  let is = |seat: &Option<SeatInfo>| seat.as_ref().is_some_and(|s| s.username == username);
+  if is(&snapshot.seats.black) {
+    Some(Color::Black)
+  } else if is(&snapshot.seats.white) {
+    Some(Color::White)
+  } else {
+    None
+  }
+}
+
+fn has_humans(snapshot: &RoomSnapshot) -> bool {
+  let seated = [&snapshot.seats.black, &snapshot.seats.white];
+  seated.into_iter().flatten().any(|s| !is_bot(&s.username)) || snapshot.spectators.iter().any(|u| !is_bot(u))
+}
+
+/// Picks a color in a Swap2 choice phase by how the stones on the board look.
+pub fn swap_choice(board: &Board, phase: Option<OpeningPhase>) -> OpeningChoice {
+  let eval = ai::evaluate(board);
+  if eval > SWAP_MARGIN {
+    OpeningChoice::Black
+  } else if eval < -SWAP_MARGIN || phase != Some(OpeningPhase::ChooseAfterThree) {
+    OpeningChoice::White
+  } else {
+    // Roughly even after three stones: add two more and let the opponent decide.
+    OpeningChoice::Place2
+  }
+}
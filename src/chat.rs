@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+/// Longest accepted message, in characters.
+pub const MAX_MESSAGE_CHARS: usize = 200;
+/// Messages kept per room for late joiners.
+pub const HISTORY_LEN: usize = 50;
+/// At most `RATE_LIMIT_MESSAGES` messages per user within `RATE_LIMIT_WINDOW`.
+pub const RATE_LIMIT_MESSAGES: usize = 5;
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatChannel {
+  /// Everyone in the room.
+  #[default]
+  All,
+  /// Spectators only, so the audience can talk about the game without coaching the players.
+  Spectators,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+  pub from: String,
+  pub text: String,
+  pub channel: ChatChannel,
+  #[serde(rename = "sentAt")]
+  pub sent_at: DateTime<Utc>,
+}
+
+/// Trims `text` and checks it against the length limit.
+pub fn normalize(text: &str) -> Result<String, &'static str> {
+  let text = text.trim();
+  if text.is_empty() {
+    return Err("bad_request");
+  }
+  if text.chars().count() > MAX_MESSAGE_CHARS {
+    return Err("message_too_long");
+  }
+  Ok(text.to_string())
+}
+
+/// Sliding-window limit per user, shared by all rooms. The default is the chat message limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+  max: usize,
+  window: Duration,
+  sent: DashMap<String, VecDeque<Instant>>,
+}
+
+impl Default for RateLimiter {
+  fn default() -> Self {
+    Self::new(RATE_LIMIT_MESSAGES, RATE_LIMIT_WINDOW)
+  }
+}
+
+impl RateLimiter {
+  /// At most `max` events per user within `window`.
+  pub fn new(max: usize, window: Duration) -> Self {
+    Self {
+      max,
+      window,
+      sent: DashMap::new(),
+    }
+  }
+
+  /// Records a message from `username` at `now`, or refuses it if they're over the limit.
+  pub fn check(&self, username: &str, now: Instant) -> Result<(), &'static str> {
+    let mut sent = self.sent.entry(username.to_string()).or_default();
+    self.expire(&mut sent, now);
+    if sent.len() >= self.max {
+      return Err("rate_limited");
+    }
+    sent.push_back(now);
+    Ok(())
+  }
+
+  /// Whether `username` has used up the limit, without recording anything. For limits that only
+  /// count failures, paired with [`RateLimiter::record`].
+  pub fn is_limited(&self, username: &str, now: Instant) -> bool {
+    self.sent.get(username).is_some_and(|sent| {
+      sent.iter().filter(|t| now.saturating_duration_since(**t) < self.window).count() >= self.max
+    })
+  }
+
+  /// Counts one event for `username` at `now`.
+  pub fn record(&self, username: &str, now: Instant) {
+    let mut sent = self.sent.entry(username.to_string()).or_default();
+    self.expire(&mut sent, now);
+    sent.push_back(now);
+  }
+
+  /// Forgets users with nothing left in their window, so the map only holds recent senders.
+  pub fn prune(&self, now: Instant) {
+    self.sent.retain(|_, sent| {
+      self.expire(sent, now);
+      !sent.is_empty()
+    });
+  }
+
+  /// Number of users currently tracked.
+  pub fn tracked(&self) -> usize {
+    self.sent.len()
+  }
+
+  fn expire(&self, sent: &mut VecDeque<Instant>, now: Instant) {
+    while sent.front().is_some_and(|t| now.saturating_duration_since(*t) >= self.window) {
+      sent.pop_front();
+    }
+  }
+}
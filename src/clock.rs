@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+use crate::rooms::Color;
+
+/// Longest main time (or per-move time) a room may set: one day.
+pub const MAX_MAIN_SECS: u64 = 24 * 60 * 60;
+/// Longest Fischer increment or byo-yomi period: one hour.
+pub const MAX_EXTRA_SECS: u64 = 60 * 60;
+pub const MAX_PERIODS: u32 = 100;
+
+/// Room-level time control. All durations are in seconds.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum TimeControl {
+  /// No clock at all.
+  #[default]
+  None,
+  /// A single time bank per player; running out loses.
+  SuddenDeath { main_secs: u64 },
+  /// Time bank plus a fixed increment after every move.
+  Fischer { main_secs: u64, increment_secs: u64 },
+  /// Japanese byo-yomi: once the main time is gone, each move must be made within one period,
+  /// and every overrun uses up a period.
+  ByoYomi { main_secs: u64, periods: u32, period_secs: u64 },
+  /// Every move must be made within a fixed time.
+  PerMove { secs: u64 },
+}
+
+impl TimeControl {
+  pub fn validate(&self) -> Result<(), &'static str> {
+    let ok = match *self {
+      TimeControl::None => true,
+      TimeControl::SuddenDeath { main_secs } => (1..=MAX_MAIN_SECS).contains(&main_secs),
+      TimeControl::Fischer { main_secs, increment_secs } => {
+        (1..=MAX_MAIN_SECS).contains(&main_secs) && increment_secs <= MAX_EXTRA_SECS
+      }
+      TimeControl::ByoYomi { main_secs, periods, period_secs } => {
+        main_secs <= MAX_MAIN_SECS
+          && (1..=MAX_PERIODS).contains(&periods)
+          && (1..=MAX_EXTRA_SECS).contains(&period_secs)
+      }
+      TimeControl::PerMove { secs } => (1..=MAX_MAIN_SECS).contains(&secs),
+    };
+    if ok { Ok(()) } else { Err("invalid_time_control") }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SideClock {
+  #[serde(rename = "remainingMs")]
+  pub remaining_ms: u64,
+  #[serde(rename = "periodsLeft", skip_serializing_if = "Option::is_none")]
+  pub periods_left: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ClockView {
+  pub black: SideClock,
+  pub white: SideClock,
+  /// Side whose clock is currently running.
+  pub running: Option<Color>,
+}
+
+/// Server-side clocks for one match. Time is only charged when a side's turn ends (or when the
+/// clocks are inspected), so nothing needs to tick in the background except flag detection.
+#[derive(Debug, Clone)]
+pub struct MatchClock {
+  control: TimeControl,
+  black: SideClock,
+  white: SideClock,
+  running: Option<(Color, Instant)>,
+}
+
+impl MatchClock {
+  pub fn new(control: TimeControl) -> Option<Self> {
+    let side = match control {
+      TimeControl::None => return None,
+      TimeControl::SuddenDeath { main_secs } | TimeControl::Fischer { main_secs, .. } => SideClock {
+        remaining_ms: main_secs.saturating_mul(1000),
+        periods_left: None,
+      },
+      TimeControl::ByoYomi { main_secs, periods, .. } => SideClock {
+        remaining_ms: main_secs.saturating_mul(1000),
+        periods_left: Some(periods),
+      },
+      TimeControl::PerMove { secs } => SideClock {
+        remaining_ms: secs.saturating_mul(1000),
+        periods_left: None,
+      },
+    };
+    Some(Self {
+      control,
+      black: side,
+      white: side,
+      running: None,
+    })
+  }
+
+  fn side_mut(&mut self, color: Color) -> &mut SideClock {
+    match color {
+      Color::Black => &mut self.black,
+      Color::White => &mut self.white,
+    }
+  }
+
+  fn period_ms(&self) -> u64 {
+    match self.control {
+      TimeControl::ByoYomi { period_secs, .. } => period_secs.saturating_mul(1000),
+      _ => 0,
+    }
+  }
+
+  /// Starts `color`'s clock. Any previously running clock must have been stopped first.
+  pub fn start(&mut self, color: Color, now: Instant) {
+    if let TimeControl::PerMove { secs } = self.control {
+      self.side_mut(color).remaining_ms = secs.saturating_mul(1000);
+    }
+    self.running = Some((color, now));
+  }
+
+  /// Total time `side` may still use on its current turn.
+  fn budget_ms(&self, side: &SideClock) -> u64 {
+    let periods = u64::from(side.periods_left.unwrap_or(0));
+    side.remaining_ms.saturating_add(periods.saturating_mul(self.period_ms()))
+  }
+
+  /// Stops the running clock and charges the elapsed time to it. Returns `false` if that side
+  /// ran out of time. With `completed_move`, the move bonus (Fischer increment) is applied.
+  pub fn stop(&mut self, now: Instant, completed_move: bool) -> bool {
+    let Some((color, since)) = self.running.take() else {
+      return true;
+    };
+    let elapsed = now.saturating_duration_since(since).as_millis() as u64;
+    let period_ms = self.period_ms();
+    let control = self.control;
+    let side = self.side_mut(color);
+
+    if elapsed <= side.remaining_ms {
+      side.remaining_ms -= elapsed;
+    } else {
+      let overrun = elapsed - side.remaining_ms;
+      side.remaining_ms = 0;
+      let Some(periods) = side.periods_left else {
+        return false;
+      };
+      // Finishing inside a period keeps it; each period fully used up is lost.
+      let lost = u32::try_from(overrun / period_ms.max(1)).unwrap_or(u32::MAX);
+      if lost >= periods {
+        side.periods_left = Some(0);
+        return false;
+      }
+      side.periods_left = Some(periods - lost);
+    }
+
+    if completed_move && let TimeControl::Fischer { increment_secs, .. } = control {
+      side.remaining_ms = side.remaining_ms.saturating_add(increment_secs.saturating_mul(1000));
+    }
+    true
+  }
+
+  /// Ends the running side's turn and starts `next`'s clock. Returns `false` on a flag fall.
+  pub fn switch(&mut self, next: Color, now: Instant) -> bool {
+    let ok = self.stop(now, true);
+    self.start(next, now);
+    ok
+  }
+
+  /// Moment at which the running side's flag falls; `None` when no clock runs or the moment is
+  /// too far out to represent.
+  pub fn deadline(&self) -> Option<(Color, Instant)> {
+    let (color, since) = self.running?;
+    let side = match color {
+      Color::Black => &self.black,
+      Color::White => &self.white,
+    };
+    Some((color, since.checked_add(Duration::from_millis(self.budget_ms(side)))?))
+  }
+
+  /// Side that has run out of time as of `now`, if any.
+  pub fn flagged(&self, now: Instant) -> Option<Color> {
+    self.deadline().filter(|(_, at)| now >= *at).map(|(color, _)| color)
+  }
+
+  /// Swaps the two sides' clocks, for openings that reassign colors after play has begun.
+  pub fn swap_sides(&mut self) {
+    std::mem::swap(&mut self.black, &mut self.white);
+  }
+
+  /// Clocks as of `now`, with the running side's elapsed time already deducted.
+  pub fn view(&self, now: Instant) -> ClockView {
+    let mut probe = self.clone();
+    let running = self.running.map(|(c, _)| c);
+    probe.stop(now, false);
+    ClockView {
+      black: probe.black,
+      white: probe.white,
+      running,
+    }
+  }
+}
@@ -1,7 +1,37 @@
-use std::{env, net::SocketAddr};
+use std::{env, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::Context;
 
+use crate::piskvork::EngineSpec;
+
+/// Upper bound for `RECONNECT_GRACE_SECS`; a held seat blocks the match, so a day is plenty.
+const MAX_RECONNECT_GRACE_SECS: u64 = 24 * 3600;
+
+/// How players with equal scores are ranked on the leaderboard (`LEADERBOARD_TIES`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieMode {
+  /// Equal scores share a rank and the next rank is skipped (1, 1, 3).
+  #[default]
+  Shared,
+  /// Equal scores share a rank without gaps (1, 1, 2).
+  Dense,
+  /// Every player gets a distinct rank; ties are broken by username.
+  Unique,
+}
+
+impl FromStr for TieMode {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, ()> {
+    match s {
+      "shared" => Ok(TieMode::Shared),
+      "dense" => Ok(TieMode::Dense),
+      "unique" => Ok(TieMode::Unique),
+      _ => Err(()),
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct Config {
   pub database_url: String,
@@ -14,6 +44,14 @@ pub struct Config {
   // If refresh token remaining lifetime is <= this threshold, rotate it on /refresh.
   // Otherwise keep the same refresh token and only mint a new access token.
   pub refresh_token_rotate_threshold_secs: i64,
+  // How long a player who drops mid-game keeps their seat before the match is abandoned.
+  // 0 restores the old behaviour of forfeiting immediately.
+  pub reconnect_grace_secs: u64,
+  // Rated games a player needs within a leaderboard's period to be listed on it.
+  pub leaderboard_min_games: i64,
+  pub leaderboard_ties: TieMode,
+  // External Gomocup engines that `room.addBot` can seat.
+  pub engines: Arc<[EngineSpec]>,
   pub bind_addr: SocketAddr,
 }
 
@@ -50,6 +88,32 @@ impl Config {
         .and_then(|v| v.parse().ok())
         .unwrap_or(24 * 3600)
         .clamp(0, refresh_token_ttl_secs);
+    let reconnect_grace_secs = env::var("RECONNECT_GRACE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60)
+        .clamp(0, MAX_RECONNECT_GRACE_SECS);
+    let leaderboard_min_games = env::var("LEADERBOARD_MIN_GAMES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5);
+    let leaderboard_ties = match env::var("LEADERBOARD_TIES") {
+        Ok(v) => v
+            .parse()
+            .ok()
+            .context("invalid env LEADERBOARD_TIES (expected shared, dense or unique)")?,
+        Err(_) => TieMode::default(),
+    };
+    let engine_timeout_turn_ms = env::var("ENGINE_TIMEOUT_TURN_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5000);
+    let engines = EngineSpec::parse_list(
+        &env::var("ENGINES").unwrap_or_default(),
+        Duration::from_millis(engine_timeout_turn_ms),
+    )
+    .context("invalid env ENGINES")?
+    .into();
     let bind_addr: SocketAddr = env::var("BIND_ADDR")
         .unwrap_or_else(|_| "127.0.0.1:8080".to_string())
         .parse()
@@ -64,6 +128,10 @@ impl Config {
       access_token_ttl_secs,
       refresh_token_ttl_secs,
       refresh_token_rotate_threshold_secs,
+      reconnect_grace_secs,
+      leaderboard_min_games,
+      leaderboard_ties,
+      engines,
       bind_addr,
     })
   }
@@ -29,6 +29,8 @@ pub enum ApiError {
   Unauthorized,
   #[error("forbidden")]
   Forbidden,
+  #[error("not found")]
+  NotFound,
   #[error("username taken")]
   UsernameTaken,
   #[error("invalid credentials")]
@@ -47,6 +49,7 @@ impl ApiError {
       ApiError::BadRequest => ("bad_request", "请求参数错误"),
       ApiError::Unauthorized => ("unauthorized", "未登录或登录已失效"),
       ApiError::Forbidden => ("forbidden", "无权限执行该操作"),
+      ApiError::NotFound => ("not_found", "资源不存在"),
       ApiError::UsernameTaken => ("username_taken", "用户名已存在"),
       ApiError::InvalidCredentials => ("invalid_credentials", "账号或密码错误"),
       ApiError::TokenExpired => ("token_expired", "登录已过期，请重新登录"),
@@ -62,6 +65,7 @@ impl ApiError {
       | ApiError::InvalidCredentials
       | ApiError::TokenExpired => StatusCode::UNAUTHORIZED,
       ApiError::Forbidden => StatusCode::FORBIDDEN,
+      ApiError::NotFound => StatusCode::NOT_FOUND,
       ApiError::UsernameTaken => StatusCode::CONFLICT,
       ApiError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
       ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
@@ -0,0 +1,383 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, PgPool, Row};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+  clock::TimeControl,
+  error::ApiError,
+  opening::OpeningRule,
+  ratings::RatingChange,
+  rooms::{Color, Coord, Move},
+  rules::{ForbiddenPolicy, RuleSet},
+};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Everything recorded about a match once it is over.
+#[derive(Debug, Clone)]
+pub struct FinishedMatch {
+  pub match_id: Uuid,
+  pub room_id: Uuid,
+  pub black: String,
+  pub white: String,
+  pub rule: RuleSet,
+  pub forbidden_policy: ForbiddenPolicy,
+  pub opening: OpeningRule,
+  pub board_size: usize,
+  pub time_control: TimeControl,
+  /// Black's and White's rating changes; `None` for casual matches.
+  pub rating_changes: Option<(RatingChange, RatingChange)>,
+  pub winner: Option<Color>,
+  pub reason: String,
+  pub moves: Vec<Move>,
+  pub started_at: DateTime<Utc>,
+  pub ended_at: DateTime<Utc>,
+}
+
+impl FinishedMatch {
+  pub fn result(&self) -> &'static str {
+    match self.winner {
+      Some(Color::Black) => "black_win",
+      Some(Color::White) => "white_win",
+      None => "draw",
+    }
+  }
+}
+
+pub type HistorySink = mpsc::UnboundedSender<FinishedMatch>;
+
+/// Serde name of a unit enum variant, as stored in the text columns.
+fn enum_str<T: Serialize>(v: &T) -> String {
+  match serde_json::to_value(v) {
+    Ok(serde_json::Value::String(s)) => s,
+    other => other.map(|v| v.to_string()).unwrap_or_default(),
+  }
+}
+
+pub async fn save_match(pool: &PgPool, m: &FinishedMatch) -> anyhow::Result<()> {
+  let mut tx = pool.begin().await?;
+
+  sqlx::query(
+    r#"
+    INSERT INTO matches (
+      id, room_id, black_username, white_username, black_user_id, white_user_id,
+      rule, forbidden_policy, opening, board_size, time_control,
+      result, reason, move_count, started_at, ended_at, rated
+    )
+    VALUES (
+      $1, $2, $3, $4,
+      (SELECT id FROM users WHERE username = $3),
+      (SELECT id FROM users WHERE username = $4),
+      $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15
+    )
+    "#,
+  )
+  .bind(m.match_id)
+  .bind(m.room_id)
+  .bind(&m.black)
+  .bind(&m.white)
+  .bind(enum_str(&m.rule))
+  .bind(enum_str(&m.forbidden_policy))
+  .bind(enum_str(&m.opening))
+  .bind(m.board_size as i32)
+  .bind(serde_json::to_string(&m.time_control)?)
+  .bind(m.result())
+  .bind(&m.reason)
+  .bind(m.moves.len() as i32)
+  .bind(m.started_at)
+  .bind(m.ended_at)
+  .bind(m.rating_changes.is_some())
+  .execute(&mut *tx)
+  .await?;
+
+  for (ply, mv) in m.moves.iter().enumerate() {
+    sqlx::query(
+      r#"
+      INSERT INTO match_moves (match_id, ply, color, row, col, played_at)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      "#,
+    )
+    .bind(m.match_id)
+    .bind(ply as i32)
+    .bind(enum_str(&mv.color))
+    .bind(mv.coord.row)
+    .bind(mv.coord.col)
+    .bind(mv.played_at)
+    .execute(&mut *tx)
+    .await?;
+  }
+
+  if let Some((black, white)) = &m.rating_changes {
+    for (username, change) in [(&m.black, black), (&m.white, white)] {
+      sqlx::query(
+        r#"
+        WITH updated AS (
+          UPDATE users
+          SET rating = $2, rating_rd = $3, rating_volatility = $4
+          WHERE username = $1
+          RETURNING id
+        )
+        INSERT INTO rating_history (user_id, match_id, rating, rd, volatility, delta)
+        SELECT id, $5, $2, $3, $4, $6 FROM updated
+        "#,
+      )
+      .bind(username)
+      .bind(change.after.rating)
+      .bind(change.after.rd)
+      .bind(change.after.volatility)
+      .bind(m.match_id)
+      .bind(change.delta())
+      .execute(&mut *tx)
+      .await?;
+    }
+  }
+
+  tx.commit().await?;
+  Ok(())
+}
+
+/// Spawns the task that writes finished matches to the database and returns its sink.
+///
+/// Rooms hand matches over through the channel so that a slow or failing database never holds up
+/// the room lock; failed writes are logged and dropped.
+pub fn spawn_writer(pool: PgPool) -> HistorySink {
+  let (tx, mut rx) = mpsc::unbounded_channel::<FinishedMatch>();
+  tokio::spawn(async move {
+    while let Some(m) = rx.recv().await {
+      if let Err(e) = save_match(&pool, &m).await {
+        tracing::error!(match_id = %m.match_id, error = %e, "history: failed to save match");
+      }
+    }
+  });
+  tx
+}
+
+/// Outcome of a match from one player's point of view.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerResult {
+  Win,
+  Loss,
+  Draw,
+}
+
+impl PlayerResult {
+  fn as_str(self) -> &'static str {
+    match self {
+      PlayerResult::Win => "win",
+      PlayerResult::Loss => "loss",
+      PlayerResult::Draw => "draw",
+    }
+  }
+}
+
+/// Filters for a player's match list. Date bounds apply to the end of the match; `to` is exclusive.
+#[derive(Debug, Clone, Default)]
+pub struct MatchFilter {
+  pub result: Option<PlayerResult>,
+  pub opponent: Option<String>,
+  pub from: Option<DateTime<Utc>>,
+  pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchSummary {
+  #[serde(rename = "matchId")]
+  pub match_id: Uuid,
+  pub black: String,
+  pub white: String,
+  pub rule: String,
+  #[serde(rename = "boardSize")]
+  pub board_size: i32,
+  pub result: String,
+  pub reason: String,
+  #[serde(rename = "moveCount")]
+  pub move_count: i32,
+  #[serde(rename = "startedAt")]
+  pub started_at: DateTime<Utc>,
+  #[serde(rename = "endedAt")]
+  pub ended_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchPage {
+  pub items: Vec<MatchSummary>,
+  /// Pass back as `cursor` to fetch the next (older) page; `None` on the last page.
+  #[serde(rename = "nextCursor")]
+  pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayMove {
+  pub ply: i32,
+  pub color: String,
+  pub coord: Coord,
+  #[serde(rename = "playedAt")]
+  pub played_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchDetail {
+  #[serde(flatten)]
+  pub summary: MatchSummary,
+  #[serde(rename = "forbiddenPolicy")]
+  pub forbidden_policy: String,
+  pub opening: String,
+  #[serde(rename = "timeControl")]
+  pub time_control: serde_json::Value,
+  pub moves: Vec<ReplayMove>,
+}
+
+fn summary_from_row(row: &PgRow) -> MatchSummary {
+  MatchSummary {
+    match_id: row.get("id"),
+    black: row.get("black_username"),
+    white: row.get("white_username"),
+    rule: row.get("rule"),
+    board_size: row.get("board_size"),
+    result: row.get("result"),
+    reason: row.get("reason"),
+    move_count: row.get("move_count"),
+    started_at: row.get("started_at"),
+    ended_at: row.get("ended_at"),
+  }
+}
+
+/// Cursors are the (ended_at, id) of the last item on the previous page, which is the sort key.
+pub fn encode_cursor(ended_at: DateTime<Utc>, id: Uuid) -> String {
+  let raw = format!("{}|{}", ended_at.to_rfc3339_opts(SecondsFormat::Micros, true), id);
+  URL_SAFE_NO_PAD.encode(raw)
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, Uuid), ApiError> {
+  let raw = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| ApiError::BadRequest)?;
+  let raw = String::from_utf8(raw).map_err(|_| ApiError::BadRequest)?;
+  let (ts, id) = raw.split_once('|').ok_or(ApiError::BadRequest)?;
+  let ts = DateTime::parse_from_rfc3339(ts).map_err(|_| ApiError::BadRequest)?;
+  let id = Uuid::parse_str(id).map_err(|_| ApiError::BadRequest)?;
+  Ok((ts.with_timezone(&Utc), id))
+}
+
+/// Matches played by `username`, newest first.
+pub async fn list_user_matches(
+  pool: &PgPool,
+  username: &str,
+  filter: &MatchFilter,
+  cursor: Option<&str>,
+  limit: i64,
+) -> Result<MatchPage, ApiError> {
+  let limit = limit.clamp(1, MAX_PAGE_SIZE);
+  let cursor = cursor.map(decode_cursor).transpose()?;
+
+  let user_id: Option<Uuid> = sqlx::query_scalar("SELECT id FROM users WHERE username = $1")
+    .bind(username)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| ApiError::Internal)?;
+  let Some(user_id) = user_id else {
+    return Err(ApiError::NotFound);
+  };
+
+  let rows = sqlx::query(
+    r#"
+    SELECT id, black_username, white_username, rule, board_size, result, reason, move_count,
+           started_at, ended_at
+    FROM matches
+    WHERE (black_user_id = $1 OR white_user_id = $1)
+      AND ($2::text IS NULL OR $2 = CASE
+            WHEN result = 'draw' THEN 'draw'
+            WHEN (result = 'black_win') = (black_user_id IS NOT DISTINCT FROM $1) THEN 'win'
+            ELSE 'loss'
+          END)
+      AND ($3::text IS NULL OR $3 = CASE
+            WHEN black_user_id = $1 THEN white_username
+            ELSE black_username
+          END)
+      AND ($4::timestamptz IS NULL OR ended_at >= $4)
+      AND ($5::timestamptz IS NULL OR ended_at < $5)
+      AND ($6::timestamptz IS NULL OR (ended_at, id) < ($6, $7))
+    ORDER BY ended_at DESC, id DESC
+    LIMIT $8
+    "#,
+  )
+  .bind(user_id)
+  .bind(filter.result.map(PlayerResult::as_str))
+  .bind(filter.opponent.as_deref())
+  .bind(filter.from)
+  .bind(filter.to)
+  .bind(cursor.map(|(ts, _)| ts))
+  .bind(cursor.map(|(_, id)| id))
+  .bind(limit + 1)
+  .fetch_all(pool)
+  .await
+  .map_err(|_| ApiError::Internal)?;
+
+  Ok(paginate(rows.iter().map(summary_from_row).collect(), limit))
+}
+
+/// Cuts a `limit + 1` fetch down to one page; the extra row only signals that another page exists.
+pub fn paginate(mut items: Vec<MatchSummary>, limit: i64) -> MatchPage {
+  let next_cursor = if items.len() as i64 > limit {
+    items.truncate(limit as usize);
+    items.last().map(|m| encode_cursor(m.ended_at, m.match_id))
+  } else {
+    None
+  };
+  MatchPage { items, next_cursor }
+}
+
+/// A stored match with its full move list in play order.
+pub async fn get_match(pool: &PgPool, match_id: Uuid) -> Result<MatchDetail, ApiError> {
+  let row = sqlx::query(
+    r#"
+    SELECT id, black_username, white_username, rule, forbidden_policy, opening, board_size,
+           time_control, result, reason, move_count, started_at, ended_at
+    FROM matches
+    WHERE id = $1
+    "#,
+  )
+  .bind(match_id)
+  .fetch_optional(pool)
+  .await
+  .map_err(|_| ApiError::Internal)?;
+  let Some(row) = row else {
+    return Err(ApiError::NotFound);
+  };
+
+  let moves = sqlx::query(
+    r#"
+    SELECT ply, color, row, col, played_at
+    FROM match_moves
+    WHERE match_id = $1
+    ORDER BY ply
+    "#,
+  )
+  .bind(match_id)
+  .fetch_all(pool)
+  .await
+  .map_err(|_| ApiError::Internal)?
+  .iter()
+  .map(|r| ReplayMove {
+    ply: r.get("ply"),
+    color: r.get("color"),
+    coord: Coord {
+      row: r.get("row"),
+      col: r.get("col"),
+    },
+    played_at: r.get("played_at"),
+  })
+  .collect();
+
+  let time_control: String = row.get("time_control");
+  Ok(MatchDetail {
+    summary: summary_from_row(&row),
+    forbidden_policy: row.get("forbidden_policy"),
+    opening: row.get("opening"),
+    time_control: serde_json::from_str(&time_control).unwrap_or(serde_json::Value::Null),
+    moves,
+  })
+}
@@ -0,0 +1,180 @@
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, PgPool, Row};
+
+pub use crate::config::TieMode;
+use crate::error::ApiError;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+  #[default]
+  All,
+  /// Current calendar month (UTC).
+  Month,
+  /// Current ISO week, starting Monday (UTC).
+  Week,
+}
+
+impl Period {
+  /// Start of the period containing `now`; `None` for the all-time board.
+  pub fn start(self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let today = now.date_naive();
+    let first_day = match self {
+      Period::All => return None,
+      Period::Month => today.with_day(1)?,
+      Period::Week => today - Duration::days(i64::from(today.weekday().num_days_from_monday())),
+    };
+    Some(Utc.from_utc_datetime(&first_day.and_hms_opt(0, 0, 0)?))
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+  /// Current rating on the all-time board; rating gained within the period on the month and week
+  /// boards, so they reflect recent form rather than repeat the all-time order.
+  #[default]
+  Rating,
+  /// Rated wins within the period.
+  Wins,
+}
+
+/// Which board to rank, and how.
+#[derive(Debug, Clone, Copy)]
+pub struct BoardSpec {
+  pub period: Period,
+  pub metric: Metric,
+  pub ties: TieMode,
+  pub min_games: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderboardEntry {
+  pub rank: i64,
+  pub username: String,
+  pub rating: f64,
+  pub rd: f64,
+  /// Net rating change from the rated games in the period.
+  #[serde(rename = "ratingChange")]
+  pub rating_change: f64,
+  pub games: i64,
+  pub wins: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Leaderboard {
+  pub items: Vec<LeaderboardEntry>,
+  /// Number of ranked players on this board.
+  pub total: i64,
+  /// The caller's own entry, when authenticated and ranked.
+  pub me: Option<LeaderboardEntry>,
+}
+
+fn entry_from_row(row: &PgRow) -> LeaderboardEntry {
+  LeaderboardEntry {
+    rank: row.get("rank"),
+    username: row.get("username"),
+    rating: row.get("rating"),
+    rd: row.get("rating_rd"),
+    rating_change: row.get("rating_change"),
+    games: row.get("games"),
+    wins: row.get("wins"),
+  }
+}
+
+/// Ranked players for a board. Only rated matches count, and players need `min_games` of them
+/// within the period to appear.
+///
+/// Parameters: $1 period start (nullable), $2 minimum games.
+fn ranked_cte(period: Period, metric: Metric, ties: TieMode) -> String {
+  let key = match (metric, period) {
+    (Metric::Rating, Period::All) => "u.rating",
+    (Metric::Rating, _) => "s.rating_change",
+    (Metric::Wins, _) => "s.wins",
+  };
+  let rank = match ties {
+    TieMode::Shared => format!("RANK() OVER (ORDER BY {key} DESC)"),
+    TieMode::Dense => format!("DENSE_RANK() OVER (ORDER BY {key} DESC)"),
+    TieMode::Unique => format!("ROW_NUMBER() OVER (ORDER BY {key} DESC, u.username)"),
+  };
+  format!(
+    r#"
+    WITH games AS (
+      SELECT id AS match_id, black_user_id AS user_id, result = 'black_win' AS won
+      FROM matches
+      WHERE rated AND black_user_id IS NOT NULL AND ($1::timestamptz IS NULL OR ended_at >= $1)
+      UNION ALL
+      SELECT id AS match_id, white_user_id AS user_id, result = 'white_win' AS won
+      FROM matches
+      WHERE rated AND white_user_id IS NOT NULL AND ($1::timestamptz IS NULL OR ended_at >= $1)
+    ),
+    stats AS (
+      SELECT g.user_id, COUNT(*) AS games, COUNT(*) FILTER (WHERE g.won) AS wins,
+             COALESCE(SUM(h.delta), 0) AS rating_change
+      FROM games g
+      LEFT JOIN rating_history h ON h.user_id = g.user_id AND h.match_id = g.match_id
+      GROUP BY g.user_id
+    ),
+    ranked AS (
+      SELECT u.username, u.rating, u.rating_rd, s.rating_change, s.games, s.wins, {rank} AS rank
+      FROM stats s
+      JOIN users u ON u.id = s.user_id
+      WHERE s.games >= $2
+    )
+    "#
+  )
+}
+
+pub async fn load(
+  pool: &PgPool,
+  spec: BoardSpec,
+  offset: i64,
+  limit: i64,
+  caller: Option<&str>,
+) -> Result<Leaderboard, ApiError> {
+  let since = spec.period.start(Utc::now());
+  let min_games = spec.min_games;
+  let cte = ranked_cte(spec.period, spec.metric, spec.ties);
+  let limit = limit.clamp(1, MAX_PAGE_SIZE);
+  let offset = offset.max(0);
+
+  let items = sqlx::query(&format!(
+    "{cte} SELECT * FROM ranked ORDER BY rank, username LIMIT $3 OFFSET $4"
+  ))
+  .bind(since)
+  .bind(min_games)
+  .bind(limit)
+  .bind(offset)
+  .fetch_all(pool)
+  .await
+  .map_err(|_| ApiError::Internal)?
+  .iter()
+  .map(entry_from_row)
+  .collect();
+
+  let total: i64 = sqlx::query_scalar(&format!("{cte} SELECT COUNT(*) FROM ranked"))
+    .bind(since)
+    .bind(min_games)
+    .fetch_one(pool)
+    .await
+    .map_err(|_| ApiError::Internal)?;
+
+  let me = match caller {
+    Some(username) => sqlx::query(&format!("{cte} SELECT * FROM ranked WHERE username = $3"))
+      .bind(since)
+      .bind(min_games)
+      .bind(username)
+      .fetch_optional(pool)
+      .await
+      .map_err(|_| ApiError::Internal)?
+      .as_ref()
+      .map(entry_from_row),
+    None => None,
+  };
+
+  Ok(Leaderboard { items, total, me })
+}
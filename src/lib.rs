@@ -1,9 +1,24 @@
+pub mod ai;
+pub mod analysis;
 pub mod api;
 pub mod auth;
+pub mod board;
+pub mod bots;
+pub mod chat;
+pub mod clock;
 pub mod config;
 pub mod db;
 pub mod error;
+pub mod history;
+pub mod leaderboard;
+pub mod matchmaking;
+pub mod opening;
+pub mod piskvork;
 pub mod protocol;
+pub mod ratings;
 pub mod rooms;
+pub mod rules;
+pub mod solver;
+pub mod tournament;
 pub mod ws;
 
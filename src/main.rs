@@ -1,5 +1,5 @@
 use axum::{routing::get, Router};
-use server::{api, config::Config, db, rooms, ws};
+use server::{api, config::Config, db, history, matchmaking::Matchmaker, rooms, ws};
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing_subscriber::EnvFilter;
 
@@ -22,9 +22,18 @@ async fn main() -> anyhow::Result<()> {
   db::migrate(&pool).await?;
 
   let hub = ws::Hub::default();
-  let rooms = rooms::RoomService::default();
+  let rooms = rooms::RoomService::with_history(history::spawn_writer(pool.clone()));
+  let matchmaker = Matchmaker::default();
+  ws::spawn_ticker(hub.clone(), rooms.clone(), matchmaker.clone());
+  ws::spawn_lobby_forwarder(hub.clone(), rooms.clone());
 
-  let app_state = api::AppState { cfg, pool, hub, rooms };
+  let app_state = api::AppState {
+    cfg,
+    pool,
+    hub,
+    rooms,
+    matchmaker,
+  };
 
   let app = Router::new()
       .route("/healthz", get(api::healthz))
@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::time::Instant;
+
+use crate::rooms::RoomOptions;
+
+/// Rating gap accepted as soon as a player joins the queue.
+const BASE_WINDOW: f64 = 100.0;
+/// How much the accepted gap grows for every second spent waiting.
+const WIDEN_PER_SEC: f64 = 10.0;
+const MAX_WINDOW: f64 = 800.0;
+
+#[derive(Debug, Clone)]
+struct Ticket {
+  username: String,
+  rating: f64,
+  options: RoomOptions,
+  since: Instant,
+}
+
+impl Ticket {
+  fn window(&self, now: Instant) -> f64 {
+    let waited = now.saturating_duration_since(self.since).as_secs_f64();
+    (BASE_WINDOW + WIDEN_PER_SEC * waited).min(MAX_WINDOW)
+  }
+}
+
+/// Two queued players to be seated together. `first` has waited longer.
+#[derive(Debug, Clone)]
+pub struct Pairing {
+  pub first: String,
+  pub second: String,
+  pub options: RoomOptions,
+  tickets: [Ticket; 2],
+}
+
+/// Queue of players waiting for an opponent. Only players asking for the same room options are
+/// paired, and only when their rating gap fits both players' current windows.
+#[derive(Debug, Clone, Default)]
+pub struct Matchmaker {
+  queue: Arc<Mutex<Vec<Ticket>>>,
+}
+
+impl Matchmaker {
+  /// Adds `username` to the queue, replacing any earlier ticket of theirs.
+  pub fn enqueue(&self, username: &str, rating: f64, options: RoomOptions, now: Instant) {
+    let mut queue = self.queue.lock().unwrap();
+    queue.retain(|t| t.username != username);
+    queue.push(Ticket {
+      username: username.to_string(),
+      rating,
+      options,
+      since: now,
+    });
+  }
+
+  /// Removes `username` from the queue. Returns whether they were queued.
+  pub fn cancel(&self, username: &str) -> bool {
+    let mut queue = self.queue.lock().unwrap();
+    let before = queue.len();
+    queue.retain(|t| t.username != username);
+    queue.len() != before
+  }
+
+  /// Puts `username`'s ticket from `pairing` back in the queue, keeping the time it has waited,
+  /// when the pair could not be seated. A newer ticket of theirs takes precedence.
+  pub fn requeue(&self, pairing: &Pairing, username: &str) {
+    let Some(ticket) = pairing.tickets.iter().find(|t| t.username == username) else {
+      return;
+    };
+    let mut queue = self.queue.lock().unwrap();
+    if !queue.iter().any(|t| t.username == username) {
+      queue.push(ticket.clone());
+    }
+  }
+
+  pub fn is_queued(&self, username: &str) -> bool {
+    self.queue.lock().unwrap().iter().any(|t| t.username == username)
+  }
+
+  /// Pairs up as many queued players as possible and removes them from the queue.
+  ///
+  /// Longest-waiting players pick first, each taking the closest-rated compatible opponent.
+  pub fn pair(&self, now: Instant) -> Vec<Pairing> {
+    let mut queue = self.queue.lock().unwrap();
+    queue.sort_by_key(|t| t.since);
+
+    let mut taken = vec![false; queue.len()];
+    let mut pairings = vec![];
+    for i in 0..queue.len() {
+      if taken[i] {
+        continue;
+      }
+      let a = &queue[i];
+      let best = (i + 1..queue.len())
+        .filter(|&j| !taken[j] && queue[j].options == a.options)
+        .filter(|&j| {
+          let gap = (queue[j].rating - a.rating).abs();
+          gap <= a.window(now) && gap <= queue[j].window(now)
+        })
+        .min_by(|&x, &y| {
+          let dx = (queue[x].rating - a.rating).abs();
+          let dy = (queue[y].rating - a.rating).abs();
+          dx.total_cmp(&dy)
+        });
+      if let Some(j) = best {
+        taken[i] = true;
+        taken[j] = true;
+        pairings.push(Pairing {
+          first: a.username.clone(),
+          second: queue[j].username.clone(),
+          options: a.options.clone(),
+          tickets: [a.clone(), queue[j].clone()],
+        });
+      }
+    }
+
+    let tickets = std::mem::take(&mut *queue);
+    *queue = tickets
+      .into_iter()
+      .zip(taken)
+      .filter_map(|(t, taken)| (!taken).then_some(t))
+      .collect();
+    pairings
+  }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+use crate::rooms::{Color, Coord};
+
+/// Opening protocol played before normal alternating moves.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OpeningRule {
+  /// Black moves first and colors never change.
+  #[default]
+  Free,
+  /// The first player places three stones; the second picks a color or places two more and
+  /// hands the choice back.
+  Swap2,
+  /// Black opens in the center and Black's second stone must be at least 3 points away from it.
+  Pro,
+  /// Like `Pro`, but Black's second stone must be at least 4 points from the center.
+  LongPro,
+}
+
+/// Placement restriction advertised to clients so they can grey out illegal points.
+#[derive(Debug, Clone, Serialize)]
+pub struct OpeningRestriction {
+  /// Black's first stone must go here.
+  pub center: Coord,
+  /// Minimum distance (in rows or columns, whichever is larger) of Black's second stone from the
+  /// center.
+  #[serde(rename = "minDistance")]
+  pub min_distance: i32,
+}
+
+impl OpeningRule {
+  pub fn initial_phase(self) -> Option<OpeningPhase> {
+    match self {
+      OpeningRule::Swap2 => Some(OpeningPhase::PlaceThree),
+      OpeningRule::Free | OpeningRule::Pro | OpeningRule::LongPro => None,
+    }
+  }
+
+  pub fn restriction(self, board_size: usize) -> Option<OpeningRestriction> {
+    let min_distance = match self {
+      OpeningRule::Pro => 3,
+      OpeningRule::LongPro => 4,
+      OpeningRule::Free | OpeningRule::Swap2 => return None,
+    };
+    let mid = (board_size / 2) as i32;
+    Some(OpeningRestriction {
+      center: Coord { row: mid, col: mid },
+      min_distance,
+    })
+  }
+
+  /// Whether the `index`-th stone of the game (0-based) may be placed at `coord`.
+  pub fn permits(self, board_size: usize, index: usize, coord: &Coord) -> bool {
+    let Some(r) = self.restriction(board_size) else {
+      return true;
+    };
+    let dist = (coord.row - r.center.row).abs().max((coord.col - r.center.col).abs());
+    match index {
+      0 => dist == 0,
+      2 => dist >= r.min_distance,
+      _ => true,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OpeningPhase {
+  /// First player places black, white, black.
+  PlaceThree,
+  /// Second player chooses black, white, or to place two more stones.
+  ChooseAfterThree,
+  /// Second player places white, black.
+  PlaceTwo,
+  /// First player chooses black or white.
+  ChooseAfterFive,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OpeningChoice {
+  Black,
+  White,
+  Place2,
+}
+
+impl OpeningPhase {
+  /// The seat (identified by its color at match start) that has to act in this phase.
+  pub fn actor(self) -> Color {
+    match self {
+      OpeningPhase::PlaceThree | OpeningPhase::ChooseAfterFive => Color::Black,
+      OpeningPhase::ChooseAfterThree | OpeningPhase::PlaceTwo => Color::White,
+    }
+  }
+
+  pub fn is_choice(self) -> bool {
+    matches!(self, OpeningPhase::ChooseAfterThree | OpeningPhase::ChooseAfterFive)
+  }
+
+  pub fn allows(self, choice: OpeningChoice) -> bool {
+    match self {
+      OpeningPhase::ChooseAfterThree => true,
+      OpeningPhase::ChooseAfterFive => choice != OpeningChoice::Place2,
+      _ => false,
+    }
+  }
+
+  /// Phase after a stone is placed, given the total number of stones now on the board.
+  pub fn after_stone(self, stones: usize) -> OpeningPhase {
+    match (self, stones) {
+      (OpeningPhase::PlaceThree, 3) => OpeningPhase::ChooseAfterThree,
+      (OpeningPhase::PlaceTwo, 5) => OpeningPhase::ChooseAfterFive,
+      (phase, _) => phase,
+    }
+  }
+}
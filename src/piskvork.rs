@@ -0,0 +1,230 @@
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use anyhow::{Context, anyhow, bail};
+use futures_util::future::BoxFuture;
+use tokio::{
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+  process::{Child, ChildStdin, ChildStdout, Command},
+  time::timeout,
+};
+
+use crate::{
+  bots::{Engine, Position},
+  rooms::{Color, Coord},
+  rules::RuleSet,
+};
+
+/// How long an engine may take to load and answer `START`.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Slack on top of `timeout_turn` for process scheduling and pipe latency.
+const TURN_GRACE: Duration = Duration::from_millis(500);
+
+/// A local executable speaking the Gomocup (piskvork) protocol.
+#[derive(Debug, Clone)]
+pub struct EngineSpec {
+  /// Name clients use to pick the engine.
+  pub name: String,
+  pub path: PathBuf,
+  /// Per-move limit announced to the engine via `INFO timeout_turn` and enforced on its replies.
+  pub timeout_turn: Duration,
+}
+
+impl EngineSpec {
+  /// Parses `name=path` pairs separated by commas, e.g. `pela=/opt/pbrain-pela,embryo=./embryo`.
+  pub fn parse_list(s: &str, timeout_turn: Duration) -> anyhow::Result<Vec<EngineSpec>> {
+    s.split(',')
+      .map(str::trim)
+      .filter(|entry| !entry.is_empty())
+      .map(|entry| {
+        let (name, path) = entry
+          .split_once('=')
+          .with_context(|| format!("invalid engine entry {entry:?} (expected name=path)"))?;
+        Ok(EngineSpec {
+          name: name.trim().to_string(),
+          path: PathBuf::from(path.trim()),
+          timeout_turn,
+        })
+      })
+      .collect()
+  }
+}
+
+/// Value of `INFO rule`: bit 1 is "exactly five", bit 4 is Renju, bit 8 is Caro.
+fn rule_bits(rule: RuleSet) -> u32 {
+  match rule {
+    RuleSet::Freestyle => 0,
+    RuleSet::Standard => 1,
+    RuleSet::Renju => 4,
+    RuleSet::Caro => 8,
+  }
+}
+
+/// A running engine process and what it has been told so far.
+struct Session {
+  // Held so the process is killed when the session is dropped.
+  _child: Child,
+  stdin: ChildStdin,
+  stdout: Lines<BufReader<ChildStdout>>,
+  match_id: String,
+  board_size: usize,
+  /// Stones the engine knows about, in order, with their colors.
+  known: Vec<(Color, Coord)>,
+  /// Color the engine moved for last time.
+  own: Option<Color>,
+}
+
+impl Session {
+  async fn send(&mut self, line: &str) -> anyhow::Result<()> {
+    self.stdin.write_all(line.as_bytes()).await?;
+    self.stdin.write_all(b"\n").await?;
+    self.stdin.flush().await?;
+    Ok(())
+  }
+
+  /// Next line that is an actual answer, skipping `MESSAGE` / `DEBUG` chatter.
+  async fn answer(&mut self) -> anyhow::Result<String> {
+    loop {
+      let line = self.stdout.next_line().await?.ok_or_else(|| anyhow!("engine exited"))?;
+      let line = line.trim();
+      if line.is_empty() || line.starts_with("MESSAGE") || line.starts_with("DEBUG") {
+        continue;
+      }
+      if line.starts_with("ERROR") || line.starts_with("UNKNOWN") {
+        bail!("engine replied {line:?}");
+      }
+      return Ok(line.to_string());
+    }
+  }
+
+  async fn read_move(&mut self) -> anyhow::Result<Coord> {
+    let line = self.answer().await?;
+    let (x, y) = line.split_once(',').ok_or_else(|| anyhow!("bad move reply {line:?}"))?;
+    let col: i32 = x.trim().parse().with_context(|| format!("bad move reply {line:?}"))?;
+    let row: i32 = y.trim().parse().with_context(|| format!("bad move reply {line:?}"))?;
+    Ok(Coord { row, col })
+  }
+
+  /// The command that brings the engine from what it knows to `position` and asks for a move.
+  /// `TURN` is used when only the opponent's last move is new; anything else (takebacks, Swap2
+  /// color changes, a fresh engine mid-game) resends the whole board.
+  fn command_for(&self, position: &Position) -> String {
+    let moves: Vec<(Color, &Coord)> = position.moves.iter().map(|m| (m.color, &m.coord)).collect();
+    if moves.is_empty() {
+      return "BEGIN".to_string();
+    }
+    let known_prefix = self.known.len() + 1 == moves.len()
+      && self
+        .known
+        .iter()
+        .zip(&moves)
+        .all(|((kc, k), (mc, m))| kc == mc && k.row == m.row && k.col == m.col);
+    let (last_color, last) = moves[moves.len() - 1];
+    if known_prefix && self.own == Some(position.to_move) && last_color != position.to_move {
+      return format!("TURN {},{}", last.col, last.row);
+    }
+
+    let mut cmd = String::from("BOARD\n");
+    for (color, coord) in moves {
+      let who = if color == position.to_move { 1 } else { 2 };
+      cmd.push_str(&format!("{},{},{}\n", coord.col, coord.row, who));
+    }
+    cmd.push_str("DONE");
+    cmd
+  }
+}
+
+/// Seat driver for an external engine. The process is started on the first move and relaunched
+/// for every new game.
+pub struct PiskvorkEngine {
+  spec: EngineSpec,
+  session: Option<Session>,
+}
+
+impl PiskvorkEngine {
+  pub fn new(spec: EngineSpec) -> Self {
+    Self { spec, session: None }
+  }
+
+  async fn start(&self, position: &Position) -> anyhow::Result<Session> {
+    let mut child = Command::new(&self.spec.path)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null())
+      .kill_on_drop(true)
+      .spawn()
+      .with_context(|| format!("failed to launch engine {}", self.spec.path.display()))?;
+    let stdin = child.stdin.take().context("engine stdin unavailable")?;
+    let stdout = child.stdout.take().context("engine stdout unavailable")?;
+    let mut session = Session {
+      _child: child,
+      stdin,
+      stdout: BufReader::new(stdout).lines(),
+      match_id: position.match_id.clone(),
+      board_size: position.board_size,
+      known: vec![],
+      own: None,
+    };
+
+    session.send(&format!("START {}", position.board_size)).await?;
+    let reply = timeout(START_TIMEOUT, session.answer())
+      .await
+      .map_err(|_| anyhow!("engine did not answer START"))??;
+    if reply != "OK" {
+      bail!("engine refused START: {reply:?}");
+    }
+    session
+      .send(&format!("INFO timeout_turn {}", self.spec.timeout_turn.as_millis()))
+      .await?;
+    session.send(&format!("INFO rule {}", rule_bits(position.rule))).await?;
+    Ok(session)
+  }
+
+  async fn ask(&mut self, position: &Position) -> anyhow::Result<Coord> {
+    // A new game on the old process would start from a stale board, so relaunch instead.
+    let stale =
+      |s: &Session| s.match_id != position.match_id || s.board_size != position.board_size;
+    if self.session.as_ref().is_none_or(stale) {
+      self.session = Some(self.start(position).await?);
+    }
+    let session = self.session.as_mut().expect("session started above");
+
+    // Never wait past the player's own clock, whatever the configured per-move limit says.
+    let mut limit = self.spec.timeout_turn;
+    if let Some(left) = position.time_left_ms {
+      limit = limit.min(Duration::from_millis(left));
+      session.send(&format!("INFO time_left {left}")).await?;
+    }
+    let cmd = session.command_for(position);
+    session.send(&cmd).await?;
+    let coord = timeout(limit + TURN_GRACE, session.read_move())
+      .await
+      .map_err(|_| anyhow!("engine exceeded {} ms", limit.as_millis()))??;
+
+    session.known = position.moves.iter().map(|m| (m.color, m.coord.clone())).collect();
+    session.known.push((position.to_move, coord.clone()));
+    session.own = Some(position.to_move);
+    Ok(coord)
+  }
+}
+
+impl Engine for PiskvorkEngine {
+  fn think<'a>(&'a mut self, position: &'a Position) -> BoxFuture<'a, anyhow::Result<Coord>> {
+    Box::pin(async move {
+      let res = self.ask(position).await;
+      if res.is_err() {
+        // The engine's view of the game is unknown after a failure; start over next time.
+        self.session = None;
+      }
+      res
+    })
+  }
+
+  fn shutdown(&mut self) -> BoxFuture<'_, ()> {
+    Box::pin(async move {
+      if let Some(mut session) = self.session.take() {
+        let _ = session.send("END").await;
+      }
+    })
+  }
+}
@@ -0,0 +1,159 @@
+use std::f64::consts::PI;
+
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+
+use crate::{error::ApiError, rooms::Color};
+
+// Glicko-2 constants. Ratings are stored on the familiar Glicko scale and converted internally.
+const SCALE: f64 = 173.7178;
+const BASE_RATING: f64 = 1500.0;
+const BASE_RD: f64 = 350.0;
+const BASE_VOLATILITY: f64 = 0.06;
+/// System constant limiting how fast volatility can change.
+const TAU: f64 = 0.5;
+const EPSILON: f64 = 0.000001;
+
+/// A player's Glicko-2 rating, deviation and volatility.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Rating {
+  pub rating: f64,
+  pub rd: f64,
+  pub volatility: f64,
+}
+
+impl Default for Rating {
+  fn default() -> Self {
+    Self {
+      rating: BASE_RATING,
+      rd: BASE_RD,
+      volatility: BASE_VOLATILITY,
+    }
+  }
+}
+
+fn g(phi: f64) -> f64 {
+  1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, mu_j: f64, phi_j: f64) -> f64 {
+  1.0 / (1.0 + (-g(phi_j) * (mu - mu_j)).exp())
+}
+
+impl Rating {
+  /// Rating after one rating period against `games`, each an opponent's pre-game rating and the
+  /// score (1 win, 0.5 draw, 0 loss). With no games only the deviation grows.
+  pub fn update(self, games: &[(Rating, f64)]) -> Rating {
+    let mu = (self.rating - BASE_RATING) / SCALE;
+    let phi = self.rd / SCALE;
+    let sigma = self.volatility;
+
+    if games.is_empty() {
+      let phi_star = (phi * phi + sigma * sigma).sqrt();
+      return Rating {
+        rd: (phi_star * SCALE).min(BASE_RD),
+        ..self
+      };
+    }
+
+    let mut v_inv = 0.0;
+    let mut improvement = 0.0;
+    for (opp, score) in games {
+      let mu_j = (opp.rating - BASE_RATING) / SCALE;
+      let phi_j = opp.rd / SCALE;
+      let e = expected(mu, mu_j, phi_j);
+      v_inv += g(phi_j).powi(2) * e * (1.0 - e);
+      improvement += g(phi_j) * (score - e);
+    }
+    let v = 1.0 / v_inv;
+    let delta = v * improvement;
+
+    // New volatility via the Illinois algorithm (step 5 of Glickman's paper).
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+      let ex = x.exp();
+      ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2)) - (x - a) / (TAU * TAU)
+    };
+    let mut big_a = a;
+    let mut big_b = if delta * delta > phi * phi + v {
+      (delta * delta - phi * phi - v).ln()
+    } else {
+      let mut k = 1.0;
+      while f(a - k * TAU) < 0.0 {
+        k += 1.0;
+      }
+      a - k * TAU
+    };
+    let mut f_a = f(big_a);
+    let mut f_b = f(big_b);
+    while (big_b - big_a).abs() > EPSILON {
+      let c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+      let f_c = f(c);
+      if f_c * f_b <= 0.0 {
+        big_a = big_b;
+        f_a = f_b;
+      } else {
+        f_a /= 2.0;
+      }
+      big_b = c;
+      f_b = f_c;
+    }
+    let sigma_new = (big_a / 2.0).exp();
+
+    let phi_star = (phi * phi + sigma_new * sigma_new).sqrt();
+    let phi_new = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+    let mu_new = mu + phi_new * phi_new * improvement;
+
+    Rating {
+      rating: mu_new * SCALE + BASE_RATING,
+      rd: (phi_new * SCALE).min(BASE_RD),
+      volatility: sigma_new,
+    }
+  }
+}
+
+/// New ratings for Black and White after a single game. `winner` is `None` for a draw.
+pub fn rate_game(black: Rating, white: Rating, winner: Option<Color>) -> (Rating, Rating) {
+  let black_score = match winner {
+    Some(Color::Black) => 1.0,
+    Some(Color::White) => 0.0,
+    None => 0.5,
+  };
+  (
+    black.update(&[(white, black_score)]),
+    white.update(&[(black, 1.0 - black_score)]),
+  )
+}
+
+/// A player's rating before and after a rated match.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RatingChange {
+  pub before: Rating,
+  pub after: Rating,
+}
+
+impl RatingChange {
+  pub fn delta(&self) -> f64 {
+    self.after.rating - self.before.rating
+  }
+}
+
+/// Current rating of `username`, or `None` if there is no such user.
+pub async fn load(pool: &PgPool, username: &str) -> Result<Option<Rating>, ApiError> {
+  let row = sqlx::query(
+    r#"
+    SELECT rating, rating_rd, rating_volatility
+    FROM users
+    WHERE username = $1
+    "#,
+  )
+  .bind(username)
+  .fetch_optional(pool)
+  .await
+  .map_err(|_| ApiError::Internal)?;
+  Ok(row.map(|row| Rating {
+    rating: row.get("rating"),
+    rd: row.get("rating_rd"),
+    volatility: row.get("rating_volatility"),
+  }))
+}
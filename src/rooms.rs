@@ -1,12 +1,29 @@
-use std::sync::Arc;
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  sync::Arc,
+};
 
+use chrono::{DateTime, Utc};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use sha2::{Digest, Sha256};
+use tokio::{
+  sync::{broadcast, Mutex, MutexGuard},
+  time::{Duration, Instant},
+};
 use uuid::Uuid;
 
-use crate::protocol::EnvelopeOut;
-
-pub const BOARD_SIZE: usize = 15;
+use crate::{
+  board::{Board, DEFAULT_BOARD_SIZE, MAX_BOARD_SIZE, MIN_BOARD_SIZE},
+  bots,
+  chat::{self, ChatChannel, ChatMessage, RateLimiter},
+  clock::{ClockView, MatchClock, TimeControl},
+  history::{FinishedMatch, HistorySink},
+  opening::{OpeningChoice, OpeningPhase, OpeningRestriction, OpeningRule},
+  protocol::EnvelopeOut,
+  ratings::{self, Rating, RatingChange},
+  rules::{self, ForbiddenPolicy, RuleSet},
+};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -34,12 +51,31 @@ pub struct Coord {
 pub struct Move {
   pub color: Color,
   pub coord: Coord,
+  #[serde(rename = "playedAt")]
+  pub played_at: DateTime<Utc>,
 }
 
+type RoomHandle = Arc<Mutex<Room>>;
+type RatingTable = Arc<dashmap::DashMap<String, Rating>>;
+
+// Lobby deltas buffered per subscriber before it starts missing them.
+const LOBBY_CHANNEL_CAPACITY: usize = 256;
+// Wrong passwords or invite codes a user may try within the window before being refused.
+const JOIN_ATTEMPTS: usize = 5;
+const JOIN_ATTEMPT_WINDOW: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone)]
 pub struct RoomService {
-  rooms: Arc<dashmap::DashMap<Uuid, Arc<Mutex<Room>>>>,
+  rooms: Arc<dashmap::DashMap<Uuid, RoomHandle>>,
   user_room: Arc<dashmap::DashMap<String, Uuid>>,
+  // Where finished matches are sent for persistence; `None` keeps them in memory only.
+  history: Option<HistorySink>,
+  // Current ratings of connected players, loaded at connect time and updated as rated matches end.
+  ratings: RatingTable,
+  lobby: broadcast::Sender<LobbyEvent>,
+  chat_limits: Arc<RateLimiter>,
+  // Failed credential attempts per user, across all private rooms.
+  join_limits: Arc<RateLimiter>,
 }
 
 impl Default for RoomService {
@@ -47,7 +83,94 @@ impl Default for RoomService {
     Self {
       rooms: Arc::new(dashmap::DashMap::new()),
       user_room: Arc::new(dashmap::DashMap::new()),
+      history: None,
+      ratings: Arc::new(dashmap::DashMap::new()),
+      lobby: broadcast::channel(LOBBY_CHANNEL_CAPACITY).0,
+      chat_limits: Arc::new(RateLimiter::default()),
+      join_limits: Arc::new(RateLimiter::new(JOIN_ATTEMPTS, JOIN_ATTEMPT_WINDOW)),
+    }
+  }
+}
+
+/// Who can find and enter a room.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+  /// Listed in the lobby; anyone may join.
+  #[default]
+  Public,
+  /// Not listed, but anyone with the room id may join.
+  Unlisted,
+  /// Not listed; joining needs the room password or a current invite code.
+  Private,
+}
+
+// Invite codes avoid characters that are easy to confuse when read aloud or typed (0/O, 1/I).
+const INVITE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_CODE_LEN: usize = 6;
+
+fn new_invite_code() -> String {
+  let mut rng = rand::thread_rng();
+  (0..INVITE_CODE_LEN)
+    .map(|_| INVITE_ALPHABET[rng.gen_range(0..INVITE_ALPHABET.len())] as char)
+    .collect()
+}
+
+fn hash_room_password(room_id: Uuid, password: &str) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(room_id.as_bytes());
+  hasher.update(password.as_bytes());
+  hex::encode(hasher.finalize())
+}
+
+/// Per-room settings chosen at `room.create` time.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RoomOptions {
+  pub rule: RuleSet,
+  pub forbidden_policy: ForbiddenPolicy,
+  pub board_size: usize,
+  pub opening: OpeningRule,
+  pub allow_takeback: bool,
+  pub time_control: TimeControl,
+  /// Rated matches update both players' ratings; casual ones don't.
+  pub rated: bool,
+  pub visibility: Visibility,
+  /// Password for private rooms. Only a hash is kept once the room exists.
+  pub password: Option<String>,
+  /// Whether players trade colors when they agree to a rematch.
+  pub swap_colors_on_rematch: bool,
+  /// Engine hints each player may ask for per match. Rated matches never allow hints.
+  pub hints_per_game: u32,
+}
+
+impl Default for RoomOptions {
+  fn default() -> Self {
+    Self {
+      rule: RuleSet::default(),
+      forbidden_policy: ForbiddenPolicy::default(),
+      board_size: DEFAULT_BOARD_SIZE,
+      opening: OpeningRule::default(),
+      allow_takeback: true,
+      time_control: TimeControl::default(),
+      rated: false,
+      visibility: Visibility::default(),
+      password: None,
+      swap_colors_on_rematch: true,
+      hints_per_game: 3,
+    }
+  }
+}
+
+impl RoomOptions {
+  pub fn validate(&self) -> Result<(), &'static str> {
+    if !(MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&self.board_size) {
+      return Err("invalid_board_size");
+    }
+    if self.password.is_some() && self.visibility != Visibility::Private {
+      return Err("invalid_room_access");
     }
+    self.time_control.validate()
   }
 }
 
@@ -62,6 +185,8 @@ pub enum RoomState {
 pub struct SeatInfo {
   pub username: String,
   pub ready: bool,
+  pub connected: bool,
+  pub rating: Option<Rating>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -69,9 +194,153 @@ pub struct RoomSnapshot {
   #[serde(rename = "roomId")]
   pub room_id: String,
   pub title: String,
+  pub owner: String,
+  pub rule: RuleSet,
+  #[serde(rename = "forbiddenPolicy")]
+  pub forbidden_policy: ForbiddenPolicy,
+  #[serde(rename = "boardSize")]
+  pub board_size: usize,
+  pub opening: OpeningRule,
+  #[serde(rename = "openingRestriction")]
+  pub opening_restriction: Option<OpeningRestriction>,
+  #[serde(rename = "allowTakeback")]
+  pub allow_takeback: bool,
+  #[serde(rename = "timeControl")]
+  pub time_control: TimeControl,
+  pub rated: bool,
+  pub visibility: Visibility,
+  #[serde(rename = "hasPassword")]
+  pub has_password: bool,
   pub seats: SeatsSnapshot,
   pub spectators: Vec<String>,
   pub state: RoomState,
+  #[serde(rename = "match")]
+  pub current_match: Option<MatchSnapshot>,
+  /// Score between the seated players over their matches in this room.
+  pub series: Option<SeriesScore>,
+  /// Player who asked for a rematch and is waiting for the opponent.
+  #[serde(rename = "rematchRequestedBy")]
+  pub rematch_requested_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesPlayer {
+  pub username: String,
+  pub wins: u32,
+}
+
+/// Running score between the two players across consecutive matches in a room.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesScore {
+  pub players: [SeriesPlayer; 2],
+  pub draws: u32,
+}
+
+impl SeriesScore {
+  fn between(a: &str, b: &str) -> Self {
+    Self {
+      players: [
+        SeriesPlayer { username: a.to_string(), wins: 0 },
+        SeriesPlayer { username: b.to_string(), wins: 0 },
+      ],
+      draws: 0,
+    }
+  }
+
+  fn is_between(&self, a: &str, b: &str) -> bool {
+    let [p, q] = &self.players;
+    (p.username == a && q.username == b) || (p.username == b && q.username == a)
+  }
+
+  fn add_result(&mut self, winner: Option<&str>) {
+    match winner {
+      Some(w) => {
+        if let Some(p) = self.players.iter_mut().find(|p| p.username == w) {
+          p.wins += 1;
+        }
+      }
+      None => self.draws += 1,
+    }
+  }
+}
+
+/// One row of the lobby's room list.
+#[derive(Debug, Clone, Serialize)]
+pub struct LobbyRoom {
+  #[serde(rename = "roomId")]
+  pub room_id: String,
+  pub title: String,
+  pub black: Option<String>,
+  pub white: Option<String>,
+  #[serde(rename = "spectatorCount")]
+  pub spectator_count: usize,
+  pub state: RoomState,
+  pub rule: RuleSet,
+  #[serde(rename = "boardSize")]
+  pub board_size: usize,
+  #[serde(rename = "timeControl")]
+  pub time_control: TimeControl,
+  pub rated: bool,
+}
+
+/// Change to the lobby's room list, pushed to clients that sent `lobby.subscribe`.
+#[derive(Debug, Clone)]
+pub enum LobbyEvent {
+  Added(LobbyRoom),
+  Updated(LobbyRoom),
+  Removed(Uuid),
+}
+
+impl LobbyEvent {
+  pub fn to_envelope(&self) -> EnvelopeOut {
+    match self {
+      LobbyEvent::Added(room) => EnvelopeOut::event("lobby.roomAdded", serde_json::to_value(room).unwrap()),
+      LobbyEvent::Updated(room) => {
+        EnvelopeOut::event("lobby.roomUpdated", serde_json::to_value(room).unwrap())
+      }
+      LobbyEvent::Removed(room_id) => EnvelopeOut::event(
+        "lobby.roomRemoved",
+        serde_json::json!({ "roomId": room_id.to_string() }),
+      ),
+    }
+  }
+}
+
+/// Full state of the running match, so late joiners and reconnecting players can render it.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchSnapshot {
+  #[serde(rename = "matchId")]
+  pub match_id: String,
+  #[serde(rename = "boardSize")]
+  pub board_size: usize,
+  pub rule: RuleSet,
+  #[serde(rename = "forbiddenPolicy")]
+  pub forbidden_policy: ForbiddenPolicy,
+  pub opening: OpeningRule,
+  #[serde(rename = "openingPhase")]
+  pub opening_phase: Option<OpeningPhase>,
+  #[serde(rename = "openingRestriction")]
+  pub opening_restriction: Option<OpeningRestriction>,
+  #[serde(rename = "timeControl")]
+  pub time_control: TimeControl,
+  pub clocks: Option<ClockView>,
+  pub turn: Color,
+  pub moves: Vec<Move>,
+  /// Color of the player with a pending draw offer.
+  #[serde(rename = "drawOffer")]
+  pub draw_offer: Option<Color>,
+  /// Color of the player waiting on a takeback answer.
+  #[serde(rename = "undoRequest")]
+  pub undo_request: Option<Color>,
+  /// Hints each side may still ask for; `None` when the match doesn't allow hints.
+  #[serde(rename = "hintsLeft")]
+  pub hints_left: Option<HintsLeft>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HintsLeft {
+  pub black: u32,
+  pub white: u32,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -84,6 +353,18 @@ pub struct SeatsSnapshot {
 struct Seat {
   username: String,
   ready: bool,
+  // Set while the player's socket is gone; the seat is held until this moment.
+  disconnected_until: Option<Instant>,
+}
+
+impl Seat {
+  fn new(username: &str) -> Self {
+    Self {
+      username: username.to_string(),
+      ready: false,
+      disconnected_until: None,
+    }
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -97,17 +378,48 @@ struct Match {
   match_id: Uuid,
   turn: Color,
   moves: Vec<Move>,
-  board: [[u8; BOARD_SIZE]; BOARD_SIZE],
+  board: Board,
+  // Swap2 and similar protocols; `None` once normal play has begun.
+  opening: Option<OpeningPhase>,
+  // Username of the player with an outstanding draw offer.
+  draw_offer: Option<String>,
+  // Username of the player waiting for the opponent to approve a takeback.
+  undo_request: Option<String>,
+  // Stones fixed by the opening protocol; takebacks never go below this.
+  opening_stones: usize,
+  clock: Option<MatchClock>,
+  // Players as they currently sit; kept here because seats may already be vacated when the
+  // match ends.
+  black: String,
+  white: String,
+  started_at: DateTime<Utc>,
+  // Hints used so far, by username.
+  hints_used: HashMap<String, u32>,
 }
 
 #[derive(Debug, Clone)]
 struct Room {
   room_id: Uuid,
   title: String,
+  options: RoomOptions,
   seats: Seats,
   spectators: Vec<String>,
   state: RoomState,
   current_match: Option<Match>,
+  history: Option<HistorySink>,
+  ratings: RatingTable,
+  lobby: broadcast::Sender<LobbyEvent>,
+  // Moderator of the room: starts as the creator and passes on when they leave.
+  owner: String,
+  // Users the owner has banned from rejoining.
+  banned: HashSet<String>,
+  // Most recent chat messages, oldest first.
+  chat: VecDeque<ChatMessage>,
+  series: Option<SeriesScore>,
+  // Username of the player waiting for the opponent to accept a rematch.
+  rematch_request: Option<String>,
+  password_hash: Option<String>,
+  invite_code: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -118,6 +430,36 @@ pub enum SeatKind {
 }
 
 impl RoomService {
+  /// A service that sends every finished match to `history` for persistence.
+  pub fn with_history(history: HistorySink) -> Self {
+    Self {
+      history: Some(history),
+      ..Default::default()
+    }
+  }
+
+  /// Records `username`'s current rating so seats and rated matches can use it.
+  pub fn set_rating(&self, username: &str, rating: Rating) {
+    self.ratings.insert(username.to_string(), rating);
+  }
+
+  /// Caches `username`'s stored rating unless one is cached already. A cached rating can be
+  /// ahead of the database while a finished match waits for the history writer, so a reconnect
+  /// must not roll it back.
+  pub fn seed_rating(&self, username: &str, rating: Rating) {
+    self.ratings.entry(username.to_string()).or_insert(rating);
+  }
+
+  /// Drops `username`'s cached rating if they hold no seat or spot in any room. Called when their
+  /// last connection closes.
+  pub fn forget_rating(&self, username: &str) {
+    self.ratings.remove_if(username, |_, _| !self.user_room.contains_key(username));
+  }
+
+  pub fn rating(&self, username: &str) -> Option<Rating> {
+    self.ratings.get(username).map(|r| *r)
+  }
+
   pub fn debug_room_ids(&self) -> Vec<String> {
     let mut ids: Vec<String> = self.rooms.iter().map(|e| e.key().to_string()).collect();
     ids.sort();
@@ -129,7 +471,26 @@ impl RoomService {
   }
 
   pub async fn create_room(&self, username: &str, title: String) -> (Uuid, RoomSnapshot) {
+    self
+      .create_room_with_options(username, title, RoomOptions::default())
+      .await
+      .expect("default room options are valid")
+  }
+
+  pub async fn create_room_with_options(
+    &self,
+    username: &str,
+    title: String,
+    options: RoomOptions,
+  ) -> Result<(Uuid, RoomSnapshot), &'static str> {
+    options.validate()?;
+    let mut options = options;
     let room_id = Uuid::new_v4();
+    let password_hash = options
+      .password
+      .take()
+      .filter(|p| !p.is_empty())
+      .map(|p| hash_room_password(room_id, &p));
     let room = Room {
       room_id,
       title: if title.trim().is_empty() {
@@ -137,25 +498,88 @@ impl RoomService {
       } else {
         title.trim().to_string()
       },
+      options,
       seats: Seats {
-        black: Some(Seat {
-          username: username.to_string(),
-          ready: false,
-        }),
+        black: Some(Seat::new(username)),
         white: None,
       },
       spectators: vec![],
       state: RoomState::Waiting,
       current_match: None,
+      history: self.history.clone(),
+      ratings: self.ratings.clone(),
+      lobby: self.lobby.clone(),
+      owner: username.to_string(),
+      banned: HashSet::new(),
+      chat: VecDeque::new(),
+      series: None,
+      rematch_request: None,
+      password_hash,
+      invite_code: None,
     };
+    if room.listed() {
+      let _ = self.lobby.send(LobbyEvent::Added(room.lobby_entry()));
+    }
 
     self.user_room.insert(username.to_string(), room_id);
     self.rooms.insert(room_id, Arc::new(Mutex::new(room)));
     let snapshot = self.snapshot(room_id).await.unwrap();
-    (room_id, snapshot)
+    Ok((room_id, snapshot))
+  }
+
+  /// Creates a room for two matched players, seats them and starts the match right away.
+  /// Neither player may be in a room already.
+  pub async fn create_paired_room(
+    &self,
+    black: &str,
+    white: &str,
+    title: String,
+    options: RoomOptions,
+  ) -> Result<(Uuid, RoomSnapshot, Option<EnvelopeOut>), &'static str> {
+    let (room_id, _) = self.create_room_with_options(black, title, options).await?;
+    let seated = async {
+      self.join_room(white, room_id).await?;
+      self.take_seat(white, SeatKind::White).await?;
+      self.set_ready(black, true).await?;
+      self.set_ready(white, true).await
+    };
+    match seated.await {
+      Ok((_, snapshot, start)) => Ok((room_id, snapshot, start)),
+      Err(code) => {
+        // Take both players back out so a half-built room doesn't outlive the failed pairing.
+        for u in [white, black] {
+          if self.room_id_for_user(u) == Some(room_id) {
+            let _ = self.leave_room(u).await;
+          }
+        }
+        Err(code)
+      }
+    }
   }
 
   pub async fn join_room(&self, username: &str, room_id: Uuid) -> Result<RoomSnapshot, &'static str> {
+    self.join_room_with_credential(username, room_id, &[]).await
+  }
+
+  /// Checks whether `username` may enter `room_id` with any of `credentials` (passwords or invite
+  /// codes), without joining.
+  pub async fn check_room_access(
+    &self,
+    username: &str,
+    room_id: Uuid,
+    credentials: &[&str],
+  ) -> Result<(), &'static str> {
+    let room = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let room = room.lock().await;
+    self.admit(&room, username, credentials)
+  }
+
+  pub async fn join_room_with_credential(
+    &self,
+    username: &str,
+    room_id: Uuid,
+    credentials: &[&str],
+  ) -> Result<RoomSnapshot, &'static str> {
     let room = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
     let mut room = room.lock().await;
 
@@ -166,76 +590,60 @@ impl RoomService {
       self.user_room.insert(username.to_string(), room_id);
       return Ok(room.snapshot());
     }
+    self.admit(&room, username, credentials)?;
 
     room.spectators.push(username.to_string());
     self.user_room.insert(username.to_string(), room_id);
+    room.publish_lobby();
     Ok(room.snapshot())
   }
 
+  /// [`Room::admits`], with wrong credentials counted against the user so invite codes and
+  /// passwords can't be guessed at speed.
+  fn admit(&self, room: &Room, username: &str, credentials: &[&str]) -> Result<(), &'static str> {
+    let now = Instant::now();
+    let guessing = !credentials.is_empty() && room.options.visibility == Visibility::Private;
+    if guessing && self.join_limits.is_limited(username, now) {
+      return Err("rate_limited");
+    }
+    let res = room.admits(username, credentials);
+    if guessing && res == Err("room_locked") {
+      self.join_limits.record(username, now);
+    }
+    res
+  }
+
   pub async fn leave_room(&self, username: &str) -> Option<(RoomSnapshot, Vec<EnvelopeOut>)> {
     let room_id = self.user_room.remove(username).map(|(_, id)| id)?;
     let room = self.rooms.get(&room_id)?.clone();
     let mut room = room.lock().await;
+    let events = room.remove_member(username);
+    let snapshot = room.snapshot();
+    self.close_if_empty(room_id, room);
+    Some((snapshot, events))
+  }
 
-    // Remove from seats/spectators
-    if room.seats.black.as_ref().map(|s| s.username.as_str()) == Some(username) {
-      room.seats.black = None;
-    }
-    if room.seats.white.as_ref().map(|s| s.username.as_str()) == Some(username) {
-      room.seats.white = None;
-    }
-    room.spectators.retain(|u| u != username);
-
-    let mut events = vec![];
+  /// Takes `username` out of `room`, which the caller has already locked, so that nothing can
+  /// happen in the room between the caller's checks and the removal.
+  fn evict(&self, room_id: Uuid, room: &mut Room, username: &str) -> Vec<EnvelopeOut> {
+    self.user_room.remove_if(username, |_, id| *id == room_id);
+    room.remove_member(username)
+  }
 
-    // If match is playing and leaver was a seat, end match as disconnect.
-    if matches!(room.state, RoomState::Playing) && room.current_match.is_some() {
-      if let Some(m) = &room.current_match {
-        // Determine winner: remaining seat if any; else draw.
-        let winner = if room.seats.black.is_some() && room.seats.white.is_none() {
-          Some(Color::Black)
-        } else if room.seats.white.is_some() && room.seats.black.is_none() {
-          Some(Color::White)
-        } else {
-          None
-        };
-        events.push(EnvelopeOut::event(
-          "match.over",
-          serde_json::json!({
-            "matchId": m.match_id.to_string(),
-            "result": match winner {
-              Some(Color::Black) => "black_win",
-              Some(Color::White) => "white_win",
-              None => "draw",
-            },
-            "winner": winner.map(|c| match c { Color::Black => "black", Color::White => "white" }),
-            "reason": "disconnect"
-          }),
-        ));
-      }
-      room.state = RoomState::Waiting;
-      room.current_match = None;
-      if let Some(s) = &mut room.seats.black {
-        s.ready = false;
-      }
-      if let Some(s) = &mut room.seats.white {
-        s.ready = false;
-      }
+  /// Publishes the room's lobby entry after someone left, or drops the room if it is now empty.
+  /// Takes the guard so the room is unlocked before it leaves the map.
+  fn close_if_empty(&self, room_id: Uuid, room: MutexGuard<'_, Room>) {
+    if !room.is_empty() {
+      room.publish_lobby();
+      return;
     }
-
-    // If room becomes empty, drop it.
-    let empty = room.seats.black.is_none() && room.seats.white.is_none() && room.spectators.is_empty();
-    let snapshot = room.snapshot();
+    let listed = room.listed();
     drop(room);
-    if empty {
-      tracing::info!(
-        username = %username,
-        room_id = %room_id,
-        "room.leave: removing empty room"
-      );
-      self.rooms.remove(&room_id);
+    tracing::info!(room_id = %room_id, "room.leave: removing empty room");
+    self.rooms.remove(&room_id);
+    if listed {
+      let _ = self.lobby.send(LobbyEvent::Removed(room_id));
     }
-    Some((snapshot, events))
   }
 
   pub async fn take_seat(
@@ -265,25 +673,20 @@ impl RoomService {
         if room.seats.black.is_some() {
           return Err("seat_taken");
         }
-        room.seats.black = Some(Seat {
-          username: username.to_string(),
-          ready: false,
-        });
+        room.seats.black = Some(Seat::new(username));
       }
       SeatKind::White => {
         if room.seats.white.is_some() {
           return Err("seat_taken");
         }
-        room.seats.white = Some(Seat {
-          username: username.to_string(),
-          ready: false,
-        });
+        room.seats.white = Some(Seat::new(username));
       }
       SeatKind::Spectator => {
         room.spectators.push(username.to_string());
       }
     }
 
+    room.publish_lobby();
     Ok((room_id, room.snapshot()))
   }
 
@@ -299,45 +702,34 @@ impl RoomService {
     if matches!(room.state, RoomState::Playing) {
       return Err("invalid_room_state");
     }
+    // A rated result is applied on top of the cached rating, so it has to be the real one.
+    if ready && room.options.rated && !self.ratings.contains_key(username) {
+      return Err("rating_unavailable");
+    }
 
     let mut is_seat = false;
-    if let Some(s) = &mut room.seats.black {
-      if s.username == username {
-        s.ready = ready;
-        is_seat = true;
-      }
+    if let Some(s) = &mut room.seats.black
+      && s.username == username
+    {
+      s.ready = ready;
+      is_seat = true;
     }
-    if let Some(s) = &mut room.seats.white {
-      if s.username == username {
-        s.ready = ready;
-        is_seat = true;
-      }
+    if let Some(s) = &mut room.seats.white
+      && s.username == username
+    {
+      s.ready = ready;
+      is_seat = true;
     }
     if !is_seat {
       return Err("forbidden");
     }
 
     let mut match_start_event = None;
-    if let (Some(b), Some(w)) = (&room.seats.black, &room.seats.white) {
-      if b.ready && w.ready {
-        let match_id = Uuid::new_v4();
-        room.state = RoomState::Playing;
-        room.current_match = Some(Match {
-          match_id,
-          turn: Color::Black,
-          moves: vec![],
-          board: [[0u8; BOARD_SIZE]; BOARD_SIZE],
-        });
-        match_start_event = Some(EnvelopeOut::event(
-          "match.start",
-          serde_json::json!({
-            "matchId": match_id.to_string(),
-            "boardSize": BOARD_SIZE,
-            "turn": "black",
-            "moves": []
-          }),
-        ));
-      }
+    if let (Some(b), Some(w)) = (&room.seats.black, &room.seats.white)
+      && b.ready
+      && w.ready
+    {
+      match_start_event = room.start_match();
     }
 
     Ok((room_id, room.snapshot(), match_start_event))
@@ -355,12 +747,21 @@ impl RoomService {
     if !matches!(room.state, RoomState::Playing) {
       return Err(("invalid_room_state", "房间未在对局中"));
     }
-    let (match_id, turn) = match room.current_match.as_ref() {
-      Some(m) => (m.match_id, m.turn),
+    let (match_id, turn, opening) = match room.current_match.as_ref() {
+      Some(m) => (m.match_id, m.turn, m.opening),
       None => return Err(("match_not_found", "对局不存在")),
     };
+    if opening.is_some_and(|p| p.is_choice()) {
+      return Ok((
+        room_id,
+        serde_json::json!({ "accepted": false, "reason": "opening_choice_pending" }),
+        vec![],
+      ));
+    }
 
-    let seat_username = match turn {
+    // During the opening the placing player is fixed by the phase, not by the stone color.
+    let actor = opening.map(|p| p.actor()).unwrap_or(turn);
+    let seat_username = match actor {
       Color::Black => room.seats.black.as_ref().map(|s| s.username.as_str()),
       Color::White => room.seats.white.as_ref().map(|s| s.username.as_str()),
     };
@@ -372,14 +773,26 @@ impl RoomService {
       ));
     }
 
+    // The ticker may not have noticed yet that the mover's flag has fallen.
+    let now = Instant::now();
+    if let Some(flagged) = room.current_match.as_ref().and_then(|m| m.clock.as_ref()?.flagged(now)) {
+      let events = room.finish_match_with_snapshot(Some(flagged.other()), "timeout");
+      return Ok((
+        room_id,
+        serde_json::json!({ "accepted": false, "reason": "timeout" }),
+        events,
+      ));
+    }
+
+    let options = room.options.clone();
     let Some(m) = &mut room.current_match else {
       return Err(("match_not_found", "对局不存在"));
     };
 
     if coord.row < 0
       || coord.col < 0
-      || coord.row as usize >= BOARD_SIZE
-      || coord.col as usize >= BOARD_SIZE
+      || coord.row as usize >= m.board.size()
+      || coord.col as usize >= m.board.size()
     {
       return Ok((
         room_id,
@@ -398,6 +811,27 @@ impl RoomService {
       ));
     }
 
+    if !options.opening.permits(m.board.size(), m.moves.len(), &coord) {
+      return Ok((
+        room_id,
+        serde_json::json!({ "accepted": false, "reason": "opening_restricted" }),
+        vec![],
+      ));
+    }
+
+    // Renju: Black may not make double-threes, double-fours or overlines.
+    let mut forbidden = None;
+    if options.rule == RuleSet::Renju && turn == Color::Black {
+      forbidden = rules::renju_forbidden(&m.board, r, c);
+      if let (Some(f), ForbiddenPolicy::Reject) = (forbidden, options.forbidden_policy) {
+        return Ok((
+          room_id,
+          serde_json::json!({ "accepted": false, "reason": f.reason() }),
+          vec![],
+        ));
+      }
+    }
+
     m.board[r][c] = match turn {
       Color::Black => 1,
       Color::White => 2,
@@ -405,7 +839,16 @@ impl RoomService {
     m.moves.push(Move {
       color: turn,
       coord: coord.clone(),
+      played_at: Utc::now(),
     });
+    if let Some(phase) = m.opening {
+      m.opening = Some(phase.after_stone(m.moves.len()));
+    }
+    let undo_lapsed = m.undo_request.take().is_some();
+    let next_actor = m.opening.map(|p| p.actor()).unwrap_or(turn.other());
+    if let Some(clock) = &mut m.clock {
+      clock.switch(next_actor, now);
+    }
 
     let mut events = vec![];
     events.push(EnvelopeOut::event(
@@ -413,48 +856,43 @@ impl RoomService {
       serde_json::json!({
         "matchId": match_id.to_string(),
         "move": { "color": match turn { Color::Black => "black", Color::White => "white" }, "coord": coord },
-        "turn": match turn.other() { Color::Black => "black", Color::White => "white" }
+        "turn": match turn.other() { Color::Black => "black", Color::White => "white" },
+        "openingPhase": m.opening,
+        "clocks": m.clock.as_ref().map(|c| c.view(now))
       }),
     ));
-
-    let mut over_event = None;
-    if is_win(&m.board, r, c, m.board[r][c]) {
-      let winner = turn;
-      over_event = Some(EnvelopeOut::event(
-        "match.over",
-        serde_json::json!({
-          "matchId": match_id.to_string(),
-          "result": match winner { Color::Black => "black_win", Color::White => "white_win" },
-          "winner": match winner { Color::Black => "black", Color::White => "white" },
-          "reason": "five_in_a_row"
-        }),
-      ));
-    } else if m.moves.len() >= BOARD_SIZE * BOARD_SIZE {
-      over_event = Some(EnvelopeOut::event(
-        "match.over",
-        serde_json::json!({
-          "matchId": match_id.to_string(),
-          "result": "draw",
-          "winner": null,
-          "reason": "board_full"
-        }),
+    // A takeback request lapses once the opponent plays on instead of answering.
+    if undo_lapsed {
+      events.push(EnvelopeOut::event(
+        "match.undoExpired",
+        serde_json::json!({ "matchId": match_id.to_string() }),
       ));
     }
 
-    if let Some(evt) = over_event {
-      events.push(evt);
-      // Reset room to waiting for next match.
-      room.state = RoomState::Waiting;
-      room.current_match = None;
-      if let Some(s) = &mut room.seats.black {
-        s.ready = false;
-      }
-      if let Some(s) = &mut room.seats.white {
-        s.ready = false;
-      }
-      events.push(EnvelopeOut::event("room.snapshot", serde_json::to_value(room.snapshot()).unwrap()));
+    let won = options.rule.win_check().is_win(&m.board, r, c, m.board[r][c]);
+
+    let over = if let Some(f) = forbidden {
+      Some((Some(Color::White), f.reason()))
+    } else if won {
+      Some((Some(turn), "five_in_a_row"))
+    } else if m.moves.len() >= m.board.size() * m.board.size() {
+      Some((None, "board_full"))
+    } else {
+      None
+    };
+
+    if let Some((winner, reason)) = over {
+      events.extend(room.finish_match_with_snapshot(winner, reason));
     } else {
       m.turn = turn.other();
+      // A pending draw offer lapses once the opponent of the offerer plays on.
+      if m.draw_offer.as_deref().is_some_and(|u| u != username) {
+        m.draw_offer = None;
+        events.push(EnvelopeOut::event(
+          "match.drawExpired",
+          serde_json::json!({ "matchId": match_id.to_string() }),
+        ));
+      }
     }
 
     Ok((
@@ -468,84 +906,1068 @@ impl RoomService {
     ))
   }
 
-  pub async fn snapshot(&self, room_id: Uuid) -> Option<RoomSnapshot> {
-    let room = self.rooms.get(&room_id)?.clone();
-    let room = room.lock().await;
-    Some(room.snapshot())
-  }
-
-  pub fn room_id_for_user(&self, username: &str) -> Option<Uuid> {
-    self.user_room.get(username).map(|v| *v)
-  }
+  pub async fn match_opening_choice(
+    &self,
+    username: &str,
+    choice: OpeningChoice,
+  ) -> Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let room_id = *self.user_room.get(username).ok_or(("not_in_room", "未加入房间"))?;
+    let room = self.rooms.get(&room_id).ok_or(("room_not_found", "房间不存在"))?.clone();
+    let mut room = room.lock().await;
 
-  pub async fn participants(&self, room_id: Uuid) -> Vec<String> {
-    let room = self.rooms.get(&room_id).map(|v| v.clone());
-    let Some(room) = room else { return vec![]; };
-    let room = room.lock().await;
-    let mut users = vec![];
-    if let Some(s) = &room.seats.black {
-      users.push(s.username.clone());
+    if !matches!(room.state, RoomState::Playing) {
+      return Err(("invalid_room_state", "房间未在对局中"));
     }
-    if let Some(s) = &room.seats.white {
-      users.push(s.username.clone());
+    let (match_id, phase) = match room.current_match.as_ref() {
+      Some(m) => (m.match_id, m.opening),
+      None => return Err(("match_not_found", "对局不存在")),
+    };
+    let Some(phase) = phase.filter(|p| p.is_choice()) else {
+      return Err(("invalid_match_state", "当前无需选择执棋颜色"));
+    };
+    if !phase.allows(choice) {
+      return Err(("bad_request", "当前阶段不能选择该选项"));
     }
-    users.extend(room.spectators.iter().cloned());
-    users.sort();
-    users.dedup();
-    users
-  }
-}
-
-impl Room {
-  fn snapshot(&self) -> RoomSnapshot {
-    RoomSnapshot {
-      room_id: self.room_id.to_string(),
-      title: self.title.clone(),
-      seats: SeatsSnapshot {
-        black: self.seats.black.as_ref().map(|s| SeatInfo {
-          username: s.username.clone(),
-          ready: s.ready,
-        }),
-        white: self.seats.white.as_ref().map(|s| SeatInfo {
-          username: s.username.clone(),
-          ready: s.ready,
-        }),
-      },
-      spectators: self.spectators.clone(),
-      state: self.state.clone(),
+    let seat_username = match phase.actor() {
+      Color::Black => room.seats.black.as_ref().map(|s| s.username.as_str()),
+      Color::White => room.seats.white.as_ref().map(|s| s.username.as_str()),
+    };
+    if seat_username != Some(username) {
+      return Err(("not_your_turn", "还没轮到你选择"));
     }
-  }
-}
 
-fn is_win(board: &[[u8; BOARD_SIZE]; BOARD_SIZE], r: usize, c: usize, v: u8) -> bool {
-  let dirs: &[(i32, i32)] = &[(0, 1), (1, 0), (1, 1), (1, -1)];
-  for (dr, dc) in dirs {
-    let mut count = 1i32;
-    for step in 1..5 {
-      let rr = r as i32 + dr * step;
-      let cc = c as i32 + dc * step;
-      if rr < 0 || cc < 0 || rr as usize >= BOARD_SIZE || cc as usize >= BOARD_SIZE {
-        break;
-      }
-      if board[rr as usize][cc as usize] != v {
-        break;
+    // The chooser currently sits in the seat of `phase.actor()`; swap seats when they pick the
+    // other color so that `Room.seats` always reflects who plays which stones.
+    let mut swapped = false;
+    let next_phase = match (phase, choice) {
+      (_, OpeningChoice::Place2) => Some(OpeningPhase::PlaceTwo),
+      (_, picked) => {
+        let picked = match picked {
+          OpeningChoice::Black => Color::Black,
+          _ => Color::White,
+        };
+        if picked != phase.actor() {
+          let seats = &mut room.seats;
+          std::mem::swap(&mut seats.black, &mut seats.white);
+          swapped = true;
+        }
+        None
       }
-      count += 1;
+    };
+
+    let Some(m) = &mut room.current_match else {
+      return Err(("match_not_found", "对局不存在"));
+    };
+    m.opening = next_phase;
+    if swapped {
+      std::mem::swap(&mut m.black, &mut m.white);
     }
-    for step in 1..5 {
-      let rr = r as i32 - dr * step;
-      let cc = c as i32 - dc * step;
-      if rr < 0 || cc < 0 || rr as usize >= BOARD_SIZE || cc as usize >= BOARD_SIZE {
-        break;
+    if let Some(clock) = &mut m.clock {
+      // Each player's remaining time follows them to their new seat.
+      let now = Instant::now();
+      clock.stop(now, true);
+      if swapped {
+        clock.swap_sides();
       }
-      if board[rr as usize][cc as usize] != v {
-        break;
-      }
-      count += 1;
+      clock.start(next_phase.map(|p| p.actor()).unwrap_or(m.turn), now);
+    }
+    if next_phase.is_none() {
+      m.opening_stones = m.moves.len();
     }
-    if count >= 5 {
-      return true;
+    let turn = m.turn;
+
+    let events = vec![
+      EnvelopeOut::event(
+        "match.openingChoice",
+        serde_json::json!({
+          "matchId": match_id.to_string(),
+          "by": username,
+          "choice": choice,
+          "openingPhase": next_phase,
+          "turn": turn
+        }),
+      ),
+      EnvelopeOut::event("room.snapshot", serde_json::to_value(room.snapshot()).unwrap()),
+    ];
+    if swapped {
+      room.publish_lobby();
+    }
+
+    Ok((
+      room_id,
+      serde_json::json!({ "choice": choice, "openingPhase": next_phase, "turn": turn }),
+      events,
+    ))
+  }
+
+  pub async fn match_resign(
+    &self,
+    username: &str,
+  ) -> Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let (room_id, room) = self.locate(username)?;
+    let mut room = room.lock().await;
+    let color = room.playing_seat(username)?;
+
+    let events = room.finish_match_with_snapshot(Some(color.other()), "resign");
+    Ok((room_id, serde_json::json!({}), events))
+  }
+
+  /// Asks for a rematch against the same opponent after a match. Once both players have asked,
+  /// seats are swapped (unless the room disables it) and the next match starts right away.
+  pub async fn match_rematch(
+    &self,
+    username: &str,
+  ) -> Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let (room_id, room) = self.locate(username)?;
+    let mut room = room.lock().await;
+    if matches!(room.state, RoomState::Playing) {
+      return Err(("invalid_room_state", "对局进行中"));
+    }
+    if room.seat_color(username).is_none() {
+      return Err(("forbidden", "只有棋手可以请求再战"));
+    }
+    let (Some(b), Some(w)) = (&room.seats.black, &room.seats.white) else {
+      return Err(("invalid_room_state", "对手已离开"));
+    };
+    if !room.series.as_ref().is_some_and(|s| s.is_between(&b.username, &w.username)) {
+      return Err(("invalid_room_state", "尚未与该对手对局"));
+    }
+
+    match room.rematch_request.as_deref() {
+      Some(u) if u == username => Err(("rematch_already_requested", "已请求再战，请等待对方回应")),
+      Some(_) => {
+        if room.options.swap_colors_on_rematch {
+          let seats = &mut room.seats;
+          std::mem::swap(&mut seats.black, &mut seats.white);
+        }
+        let start = room.start_match();
+        let mut events = vec![EnvelopeOut::event(
+          "room.snapshot",
+          serde_json::to_value(room.snapshot()).unwrap(),
+        )];
+        events.extend(start);
+        Ok((room_id, serde_json::json!({ "started": true }), events))
+      }
+      None => {
+        room.rematch_request = Some(username.to_string());
+        let events = vec![
+          EnvelopeOut::event("match.rematchRequested", serde_json::json!({ "by": username })),
+          EnvelopeOut::event("room.snapshot", serde_json::to_value(room.snapshot()).unwrap()),
+        ];
+        Ok((room_id, serde_json::json!({ "started": false }), events))
+      }
+    }
+  }
+
+  /// Uses up one of `username`'s hints for the current match. Returns the match as it stands so
+  /// the caller can run the engine on it outside the room lock, and the `match.hintUsed` event
+  /// that lets the opponent know.
+  pub async fn match_hint(
+    &self,
+    username: &str,
+  ) -> Result<(Uuid, MatchSnapshot, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let (room_id, room) = self.locate(username)?;
+    let mut room = room.lock().await;
+    if !matches!(room.state, RoomState::Playing) {
+      return Err(("invalid_room_state", "房间未在对局中"));
+    }
+    let Some(color) = room.seat_color(username) else {
+      return Err(("forbidden", "只有棋手可以使用提示"));
+    };
+    if !room.hints_allowed() {
+      return Err(("hints_disabled", "本局不允许使用提示"));
+    }
+    if room.hints_left(username) == 0 {
+      return Err(("hint_limit_reached", "提示次数已用完"));
+    }
+    let Some(m) = &mut room.current_match else {
+      return Err(("match_not_found", "对局不存在"));
+    };
+    if m.opening.is_some_and(|p| p.is_choice()) || m.opening.map(|p| p.actor()).unwrap_or(m.turn) != color {
+      return Err(("not_your_turn", "还没轮到你"));
+    }
+    *m.hints_used.entry(username.to_string()).or_default() += 1;
+
+    let snapshot = room.match_snapshot().ok_or(("match_not_found", "对局不存在"))?;
+    let event = EnvelopeOut::event(
+      "match.hintUsed",
+      serde_json::json!({ "color": color, "hintsLeft": snapshot.hints_left }),
+    );
+    Ok((room_id, snapshot, vec![event]))
+  }
+
+  pub async fn match_offer_draw(
+    &self,
+    username: &str,
+  ) -> Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let (room_id, room) = self.locate(username)?;
+    let mut room = room.lock().await;
+    let color = room.playing_seat(username)?;
+    let Some(m) = &mut room.current_match else {
+      return Err(("match_not_found", "对局不存在"));
+    };
+
+    match m.draw_offer.as_deref() {
+      Some(u) if u == username => return Err(("draw_already_offered", "已提出和棋，请等待对方回应")),
+      // Both sides want a draw: treat the second offer as acceptance.
+      Some(_) => {
+        let events = room.finish_match_with_snapshot(None, "agreement");
+        return Ok((room_id, serde_json::json!({}), events));
+      }
+      None => {}
+    }
+    m.draw_offer = Some(username.to_string());
+    let evt = EnvelopeOut::event(
+      "match.drawOffered",
+      serde_json::json!({ "matchId": m.match_id.to_string(), "by": color }),
+    );
+    Ok((room_id, serde_json::json!({}), vec![evt]))
+  }
+
+  pub async fn match_respond_draw(
+    &self,
+    username: &str,
+    accept: bool,
+  ) -> Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let (room_id, room) = self.locate(username)?;
+    let mut room = room.lock().await;
+    let color = room.playing_seat(username)?;
+    let Some(m) = &mut room.current_match else {
+      return Err(("match_not_found", "对局不存在"));
+    };
+    if m.draw_offer.as_deref().is_none_or(|u| u == username) {
+      return Err(("no_draw_offer", "对方未提出和棋"));
+    }
+
+    if accept {
+      let events = room.finish_match_with_snapshot(None, "agreement");
+      return Ok((room_id, serde_json::json!({}), events));
+    }
+    m.draw_offer = None;
+    let evt = EnvelopeOut::event(
+      "match.drawDeclined",
+      serde_json::json!({ "matchId": m.match_id.to_string(), "by": color }),
+    );
+    Ok((room_id, serde_json::json!({}), vec![evt]))
+  }
+
+  pub async fn match_request_undo(
+    &self,
+    username: &str,
+  ) -> Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let (room_id, room) = self.locate(username)?;
+    let mut room = room.lock().await;
+    let color = room.playing_seat(username)?;
+    if !room.options.allow_takeback {
+      return Err(("takeback_disabled", "本房间不允许悔棋"));
+    }
+    let Some(m) = &mut room.current_match else {
+      return Err(("match_not_found", "对局不存在"));
+    };
+    if m.opening.is_some() {
+      return Err(("invalid_match_state", "开局阶段不能悔棋"));
+    }
+    if m.undo_request.is_some() {
+      return Err(("undo_already_requested", "已有悔棋请求等待回应"));
+    }
+    if m.undo_count(color) == 0 {
+      return Err(("nothing_to_undo", "没有可以悔的棋"));
+    }
+
+    m.undo_request = Some(username.to_string());
+    let evt = EnvelopeOut::event(
+      "match.undoRequested",
+      serde_json::json!({ "matchId": m.match_id.to_string(), "by": color }),
+    );
+    Ok((room_id, serde_json::json!({}), vec![evt]))
+  }
+
+  pub async fn match_respond_undo(
+    &self,
+    username: &str,
+    accept: bool,
+  ) -> Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)> {
+    let (room_id, room) = self.locate(username)?;
+    let mut room = room.lock().await;
+    let color = room.playing_seat(username)?;
+    let Some(m) = &mut room.current_match else {
+      return Err(("match_not_found", "对局不存在"));
+    };
+    if m.undo_request.as_deref().is_none_or(|u| u == username) {
+      return Err(("no_undo_request", "对方未请求悔棋"));
+    }
+    m.undo_request = None;
+    let requester = color.other();
+
+    if !accept {
+      let evt = EnvelopeOut::event(
+        "match.undoDeclined",
+        serde_json::json!({ "matchId": m.match_id.to_string(), "by": color }),
+      );
+      return Ok((room_id, serde_json::json!({}), vec![evt]));
+    }
+
+    // Pop back to the requester's last stone so it's their turn again.
+    for _ in 0..m.undo_count(requester) {
+      if let Some(mv) = m.moves.pop() {
+        m.board[mv.coord.row as usize][mv.coord.col as usize] = 0;
+      }
+    }
+    m.turn = requester;
+    if let Some(clock) = &mut m.clock {
+      let now = Instant::now();
+      clock.stop(now, false);
+      clock.start(requester, now);
+    }
+    let mut events = vec![EnvelopeOut::event(
+      "match.undone",
+      serde_json::json!({
+        "matchId": m.match_id.to_string(),
+        "by": requester,
+        "moves": m.moves,
+        "turn": requester
+      }),
+    )];
+    // A draw offer was made about a position that no longer exists.
+    if m.draw_offer.take().is_some() {
+      events.push(EnvelopeOut::event(
+        "match.drawExpired",
+        serde_json::json!({ "matchId": m.match_id.to_string() }),
+      ));
+    }
+    Ok((room_id, serde_json::json!({}), events))
+  }
+
+  /// Drops chat and join rate-limit entries whose window has passed. Driven by the ticker in `ws`.
+  pub fn prune_rate_limits(&self) {
+    let now = Instant::now();
+    self.chat_limits.prune(now);
+    self.join_limits.prune(now);
+  }
+
+  /// Ends every running match whose side to move has run out of time. Driven by the ticker in
+  /// `ws`, so flags fall even when nobody sends a message.
+  pub async fn expire_clocks(&self) -> Vec<(Uuid, Vec<EnvelopeOut>)> {
+    let handles: Vec<(Uuid, RoomHandle)> =
+      self.rooms.iter().map(|e| (*e.key(), e.value().clone())).collect();
+    let now = Instant::now();
+    let mut out = vec![];
+    for (room_id, room) in handles {
+      let mut room = room.lock().await;
+      let flagged = room
+        .current_match
+        .as_ref()
+        .and_then(|m| m.clock.as_ref()?.flagged(now));
+      if let Some(color) = flagged {
+        out.push((room_id, room.finish_match_with_snapshot(Some(color.other()), "timeout")));
+      }
+    }
+    out
+  }
+
+  /// Called when a player's socket drops. A player seated in a running match keeps the seat for
+  /// `grace` and the room is told they're gone; returns `None` when the caller should treat the
+  /// disconnect as an ordinary leave instead.
+  pub async fn hold_seat(&self, username: &str, grace: Duration) -> Option<(Uuid, Vec<EnvelopeOut>)> {
+    if grace.is_zero() {
+      return None;
+    }
+    let room_id = self.room_id_for_user(username)?;
+    let room = self.rooms.get(&room_id)?.clone();
+    let mut room = room.lock().await;
+    if !matches!(room.state, RoomState::Playing) {
+      return None;
+    }
+    let color = room.seat_color(username)?;
+    let match_id = room.current_match.as_ref()?.match_id;
+    let seat = match color {
+      Color::Black => room.seats.black.as_mut()?,
+      Color::White => room.seats.white.as_mut()?,
+    };
+    seat.disconnected_until = Some(Instant::now() + grace);
+
+    let events = vec![
+      EnvelopeOut::event(
+        "match.opponentDisconnected",
+        serde_json::json!({
+          "matchId": match_id.to_string(),
+          "username": username,
+          "color": color,
+          "graceSecs": grace.as_secs()
+        }),
+      ),
+      EnvelopeOut::event("room.snapshot", serde_json::to_value(room.snapshot()).unwrap()),
+    ];
+    Some((room_id, events))
+  }
+
+  /// Gives a held seat back to a player who reconnected within the grace window.
+  pub async fn resume_seat(&self, username: &str) -> Option<(Uuid, Vec<EnvelopeOut>)> {
+    let room_id = self.room_id_for_user(username)?;
+    let room = self.rooms.get(&room_id)?.clone();
+    let mut room = room.lock().await;
+    let seats = &mut room.seats;
+    let seat = [seats.black.as_mut(), seats.white.as_mut()]
+      .into_iter()
+      .flatten()
+      .find(|s| s.username == username && s.disconnected_until.is_some())?;
+    seat.disconnected_until = None;
+
+    let mut events = vec![];
+    if let Some(m) = &room.current_match {
+      events.push(EnvelopeOut::event(
+        "match.opponentReconnected",
+        serde_json::json!({ "matchId": m.match_id.to_string(), "username": username }),
+      ));
+    }
+    events.push(EnvelopeOut::event("room.snapshot", serde_json::to_value(room.snapshot()).unwrap()));
+    Some((room_id, events))
+  }
+
+  /// Releases seats whose grace window has run out, ending their match as `abandoned`.
+  pub async fn expire_held_seats(&self) -> Vec<(Uuid, Vec<EnvelopeOut>)> {
+    let handles: Vec<(Uuid, RoomHandle)> =
+      self.rooms.iter().map(|e| (*e.key(), e.value().clone())).collect();
+    let now = Instant::now();
+    let mut out = vec![];
+    for (room_id, room) in handles {
+      let mut room = room.lock().await;
+      let expired: Vec<(Color, String)> = [
+        (Color::Black, room.seats.black.as_ref()),
+        (Color::White, room.seats.white.as_ref()),
+      ]
+      .into_iter()
+      .filter_map(|(color, seat)| {
+        let seat = seat?;
+        (seat.disconnected_until? <= now).then(|| (color, seat.username.clone()))
+      })
+      .collect();
+      if expired.is_empty() {
+        continue;
+      }
+
+      // Still under the same lock, so a player who came back after the check above can't be
+      // removed by mistake.
+      let mut events = vec![];
+      if matches!(room.state, RoomState::Playing) {
+        // Nobody wins a game both players walked away from.
+        let winner = match expired.as_slice() {
+          [(color, _)] => Some(color.other()),
+          _ => None,
+        };
+        events.extend(room.finish_match(winner, "abandoned"));
+      }
+      for (_, username) in &expired {
+        events.extend(self.evict(room_id, &mut room, username));
+        // They are gone for good; a reconnect loads the rating again.
+        self.forget_rating(username);
+      }
+      events.push(EnvelopeOut::event("room.snapshot", serde_json::to_value(room.snapshot()).unwrap()));
+      self.close_if_empty(room_id, room);
+      out.push((room_id, events));
+    }
+    out
+  }
+
+  /// Full state of the match running in `room_id`, for clients that (re)join mid-game.
+  pub async fn match_state(&self, room_id: Uuid) -> Option<MatchSnapshot> {
+    let room = self.rooms.get(&room_id)?.clone();
+    let room = room.lock().await;
+    room.match_snapshot()
+  }
+
+  fn locate(&self, username: &str) -> Result<(Uuid, RoomHandle), (&'static str, &'static str)> {
+    let room_id = *self.user_room.get(username).ok_or(("not_in_room", "未加入房间"))?;
+    let room = self.rooms.get(&room_id).ok_or(("room_not_found", "房间不存在"))?.clone();
+    Ok((room_id, room))
+  }
+
+  pub async fn snapshot(&self, room_id: Uuid) -> Option<RoomSnapshot> {
+    let room = self.rooms.get(&room_id)?.clone();
+    let room = room.lock().await;
+    Some(room.snapshot())
+  }
+
+  /// Listing of public rooms for the lobby, open rooms first.
+  pub async fn lobby_list(&self) -> Vec<LobbyRoom> {
+    let handles: Vec<RoomHandle> = self.rooms.iter().map(|r| r.value().clone()).collect();
+    let mut list = Vec::with_capacity(handles.len());
+    for room in handles {
+      let room = room.lock().await;
+      if room.listed() {
+        list.push(room.lobby_entry());
+      }
+    }
+    list.sort_by(|a, b| {
+      let playing = |r: &LobbyRoom| matches!(r.state, RoomState::Playing);
+      playing(a).cmp(&playing(b)).then_with(|| a.title.cmp(&b.title))
+    });
+    list
+  }
+
+  /// Generates a fresh invite code for `username`'s private room, replacing any previous one.
+  pub async fn create_invite(&self, username: &str) -> Result<(Uuid, String), &'static str> {
+    let room_id = self.room_id_for_user(username).ok_or("not_in_room")?;
+    let room = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let mut room = room.lock().await;
+    if room.owner != username {
+      return Err("forbidden");
+    }
+    if room.options.visibility != Visibility::Private {
+      return Err("invalid_room_state");
+    }
+    let code = new_invite_code();
+    room.invite_code = Some(code.clone());
+    Ok((room_id, code))
+  }
+
+  /// Invalidates the current invite code of `username`'s room. Players already inside stay.
+  pub async fn revoke_invite(&self, username: &str) -> Result<Uuid, &'static str> {
+    let room_id = self.room_id_for_user(username).ok_or("not_in_room")?;
+    let room = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let mut room = room.lock().await;
+    if room.owner != username {
+      return Err("forbidden");
+    }
+    room.invite_code = None;
+    Ok(room_id)
+  }
+
+  /// Removes `target` from `owner`'s room. Returns the room and the snapshot after removal.
+  pub async fn kick(&self, owner: &str, target: &str) -> Result<(Uuid, RoomSnapshot), &'static str> {
+    let room_id = self.room_id_for_user(owner).ok_or("not_in_room")?;
+    let handle = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let mut room = handle.lock().await;
+    room.check_moderation(owner, target)?;
+    if self.room_id_for_user(target) != Some(room_id) {
+      return Err("not_in_room");
+    }
+    let _ = self.evict(room_id, &mut room, target);
+    let snapshot = room.snapshot();
+    self.close_if_empty(room_id, room);
+    Ok((room_id, snapshot))
+  }
+
+  /// Bans `target` from `owner`'s room, removing them if present. Returns the room, the snapshot
+  /// after removal, and whether `target` was in the room.
+  pub async fn ban(&self, owner: &str, target: &str) -> Result<(Uuid, RoomSnapshot, bool), &'static str> {
+    let room_id = self.room_id_for_user(owner).ok_or("not_in_room")?;
+    let handle = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let mut room = handle.lock().await;
+    room.check_moderation(owner, target)?;
+    room.banned.insert(target.to_string());
+    let present = self.room_id_for_user(target) == Some(room_id);
+    if present {
+      let _ = self.evict(room_id, &mut room, target);
+    }
+    let snapshot = room.snapshot();
+    self.close_if_empty(room_id, room);
+    Ok((room_id, snapshot, present))
+  }
+
+  /// Seats the bot `bot` in the empty seat of `owner`'s room. The bot starts unready; its driver
+  /// readies it like any other player.
+  pub async fn add_bot(&self, owner: &str, bot: &str) -> Result<(Uuid, RoomSnapshot), &'static str> {
+    let room_id = self.room_id_for_user(owner).ok_or("not_in_room")?;
+    let handle = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let mut room = handle.lock().await;
+    if room.owner != owner {
+      return Err("forbidden");
+    }
+    if room.options.rated {
+      return Err("bot_not_allowed");
+    }
+    if matches!(room.state, RoomState::Playing) {
+      return Err("invalid_room_state");
+    }
+    if room.seats.black.is_none() {
+      room.seats.black = Some(Seat::new(bot));
+    } else if room.seats.white.is_none() {
+      room.seats.white = Some(Seat::new(bot));
+    } else {
+      return Err("seat_taken");
+    }
+    self.user_room.insert(bot.to_string(), room_id);
+    room.publish_lobby();
+    Ok((room_id, room.snapshot()))
+  }
+
+  /// Hands ownership of `owner`'s room to `target`, who must be in the room.
+  pub async fn transfer_owner(&self, owner: &str, target: &str) -> Result<(Uuid, RoomSnapshot), &'static str> {
+    let room_id = self.room_id_for_user(owner).ok_or("not_in_room")?;
+    let room = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let mut room = room.lock().await;
+    if room.owner != owner {
+      return Err("forbidden");
+    }
+    if !room.contains(target) {
+      return Err("not_in_room");
+    }
+    if bots::is_bot(target) {
+      return Err("bad_request");
+    }
+    room.owner = target.to_string();
+    Ok((room_id, room.snapshot()))
+  }
+
+  /// Posts a chat message from `username` to their room. Returns the room, the message and who
+  /// should receive it (`None` for everyone in the room).
+  ///
+  /// While a match is running, spectators' messages are kept to the spectators channel.
+  pub async fn send_chat(
+    &self,
+    username: &str,
+    text: &str,
+    channel: ChatChannel,
+  ) -> Result<(Uuid, ChatMessage, Option<Vec<String>>), &'static str> {
+    let text = chat::normalize(text)?;
+    let room_id = self.room_id_for_user(username).ok_or("not_in_room")?;
+    let room = self.rooms.get(&room_id).ok_or("room_not_found")?.clone();
+    let mut room = room.lock().await;
+
+    let is_player = room.seat_color(username).is_some();
+    let channel = match channel {
+      ChatChannel::Spectators if is_player => return Err("forbidden"),
+      ChatChannel::All if !is_player && matches!(room.state, RoomState::Playing) => {
+        ChatChannel::Spectators
+      }
+      c => c,
+    };
+    self.chat_limits.check(username, Instant::now())?;
+
+    let message = ChatMessage {
+      from: username.to_string(),
+      text,
+      channel,
+      sent_at: Utc::now(),
+    };
+    if room.chat.len() >= chat::HISTORY_LEN {
+      room.chat.pop_front();
+    }
+    room.chat.push_back(message.clone());
+    let recipients = (channel == ChatChannel::Spectators).then(|| room.spectators.clone());
+    Ok((room_id, message, recipients))
+  }
+
+  /// Recent chat of `room_id` as `username` is allowed to see it: players don't get the
+  /// spectators channel.
+  pub async fn chat_history(&self, room_id: Uuid, username: &str) -> Vec<ChatMessage> {
+    let Some(room) = self.rooms.get(&room_id).map(|r| r.clone()) else {
+      return vec![];
+    };
+    let room = room.lock().await;
+    let is_player = room.seat_color(username).is_some();
+    room
+      .chat
+      .iter()
+      .filter(|m| !is_player || m.channel == ChatChannel::All)
+      .cloned()
+      .collect()
+  }
+
+  /// Receiver for lobby deltas. Each subscriber sees every change made after subscribing.
+  pub fn subscribe_lobby(&self) -> broadcast::Receiver<LobbyEvent> {
+    self.lobby.subscribe()
+  }
+
+  /// Whether `username` holds a seat in a match that is being played.
+  pub async fn is_playing(&self, username: &str) -> bool {
+    let Some(room_id) = self.room_id_for_user(username) else {
+      return false;
+    };
+    let Some(room) = self.rooms.get(&room_id).map(|r| r.clone()) else {
+      return false;
+    };
+    let room = room.lock().await;
+    matches!(room.state, RoomState::Playing) && room.seat_color(username).is_some()
+  }
+
+  pub fn room_id_for_user(&self, username: &str) -> Option<Uuid> {
+    self.user_room.get(username).map(|v| *v)
+  }
+
+  pub async fn participants(&self, room_id: Uuid) -> Vec<String> {
+    let room = self.rooms.get(&room_id).map(|v| v.clone());
+    let Some(room) = room else { return vec![]; };
+    let room = room.lock().await;
+    let mut users = vec![];
+    if let Some(s) = &room.seats.black {
+      users.push(s.username.clone());
+    }
+    if let Some(s) = &room.seats.white {
+      users.push(s.username.clone());
+    }
+    users.extend(room.spectators.iter().cloned());
+    users.sort();
+    users.dedup();
+    users
+  }
+}
+
+impl Match {
+  /// How many moves a takeback for `color` removes: its own last stone, plus the opponent's reply
+  /// if there is one. Zero when nothing of `color` can be taken back.
+  fn undo_count(&self, color: Color) -> usize {
+    let undoable = &self.moves[self.opening_stones.min(self.moves.len())..];
+    match undoable.iter().rev().position(|mv| mv.color == color) {
+      Some(i) if i <= 1 => i + 1,
+      _ => 0,
+    }
+  }
+}
+
+impl Room {
+  fn seat_color(&self, username: &str) -> Option<Color> {
+    if self.seats.black.as_ref().map(|s| s.username.as_str()) == Some(username) {
+      Some(Color::Black)
+    } else if self.seats.white.as_ref().map(|s| s.username.as_str()) == Some(username) {
+      Some(Color::White)
+    } else {
+      None
+    }
+  }
+
+  /// Color of `username` in the running match, or the error to report if there is none.
+  fn playing_seat(&self, username: &str) -> Result<Color, (&'static str, &'static str)> {
+    if !matches!(self.state, RoomState::Playing) || self.current_match.is_none() {
+      return Err(("invalid_room_state", "房间未在对局中"));
+    }
+    self.seat_color(username).ok_or(("forbidden", "只有对局双方可以操作"))
+  }
+
+  /// Starts a match between the two seated players and returns its `match.start` event.
+  fn start_match(&mut self) -> Option<EnvelopeOut> {
+    let black = self.seats.black.as_ref()?.username.clone();
+    let white = self.seats.white.as_ref()?.username.clone();
+    let opening = self.options.opening.initial_phase();
+    let mut clock = MatchClock::new(self.options.time_control);
+    if let Some(clock) = &mut clock {
+      clock.start(opening.map(|p| p.actor()).unwrap_or(Color::Black), Instant::now());
+    }
+    self.state = RoomState::Playing;
+    self.rematch_request = None;
+    self.current_match = Some(Match {
+      match_id: Uuid::new_v4(),
+      turn: Color::Black,
+      moves: vec![],
+      board: Board::new(self.options.board_size),
+      opening,
+      draw_offer: None,
+      undo_request: None,
+      opening_stones: 0,
+      clock,
+      black,
+      white,
+      started_at: Utc::now(),
+      hints_used: HashMap::new(),
+    });
+    self.publish_lobby();
+    self
+      .match_snapshot()
+      .map(|m| EnvelopeOut::event("match.start", serde_json::to_value(m).unwrap()))
+  }
+
+  /// Ends the current match and resets the room for the next one.
+  fn finish_match(&mut self, winner: Option<Color>, reason: &str) -> Option<EnvelopeOut> {
+    let m = self.current_match.take()?;
+    let match_id = m.match_id;
+    let rating_changes = self.options.rated.then(|| self.rate(&m, winner)).flatten();
+    let winner_name = winner.map(|c| match c {
+      Color::Black => m.black.as_str(),
+      Color::White => m.white.as_str(),
+    });
+    match &mut self.series {
+      Some(series) if series.is_between(&m.black, &m.white) => series.add_result(winner_name),
+      _ => {
+        let mut series = SeriesScore::between(&m.black, &m.white);
+        series.add_result(winner_name);
+        self.series = Some(series);
+      }
+    }
+    self.record(m, winner, reason, rating_changes);
+    self.state = RoomState::Waiting;
+    if let Some(s) = &mut self.seats.black {
+      s.ready = false;
+    }
+    if let Some(s) = &mut self.seats.white {
+      s.ready = false;
+    }
+    self.publish_lobby();
+    Some(EnvelopeOut::event(
+      "match.over",
+      serde_json::json!({
+        "matchId": match_id.to_string(),
+        "result": match winner {
+          Some(Color::Black) => "black_win",
+          Some(Color::White) => "white_win",
+          None => "draw",
+        },
+        "winner": winner.map(|c| match c { Color::Black => "black", Color::White => "white" }),
+        "reason": reason,
+        "ratings": rating_changes.map(|(black, white)| serde_json::json!({
+          "black": black,
+          "white": white
+        }))
+      }),
+    ))
+  }
+
+  fn lobby_entry(&self) -> LobbyRoom {
+    LobbyRoom {
+      room_id: self.room_id.to_string(),
+      title: self.title.clone(),
+      black: self.seats.black.as_ref().map(|s| s.username.clone()),
+      white: self.seats.white.as_ref().map(|s| s.username.clone()),
+      spectator_count: self.spectators.len(),
+      state: self.state.clone(),
+      rule: self.options.rule,
+      board_size: self.options.board_size,
+      time_control: self.options.time_control,
+      rated: self.options.rated,
+    }
+  }
+
+  /// Who inherits ownership when the owner leaves: seated players first, then the longest-staying
+  /// spectator.
+  fn successor(&self) -> Option<String> {
+    let seated = [&self.seats.black, &self.seats.white];
+    let mut candidates = seated
+      .into_iter()
+      .flatten()
+      .map(|s| &s.username)
+      .chain(&self.spectators);
+    // Bots can't moderate, so they only inherit a room nobody else is in.
+    candidates
+      .clone()
+      .find(|u| !bots::is_bot(u))
+      .or_else(|| candidates.next())
+      .cloned()
+  }
+
+  /// Removes `username` from the seats and spectators, passing ownership on and ending a running
+  /// match they were playing.
+  fn remove_member(&mut self, username: &str) -> Vec<EnvelopeOut> {
+    let was_seated = self.seat_color(username).is_some();
+    if self.seats.black.as_ref().map(|s| s.username.as_str()) == Some(username) {
+      self.seats.black = None;
+    }
+    if self.seats.white.as_ref().map(|s| s.username.as_str()) == Some(username) {
+      self.seats.white = None;
+    }
+    self.spectators.retain(|u| u != username);
+    if was_seated {
+      self.rematch_request = None;
+    }
+    if self.owner == username
+      && let Some(next) = self.successor()
+    {
+      self.owner = next;
+    }
+
+    // If match is playing and leaver was a seat, end match as disconnect.
+    if !(was_seated && matches!(self.state, RoomState::Playing)) {
+      return vec![];
+    }
+    // Determine winner: remaining seat if any; else draw.
+    let winner = if self.seats.black.is_some() && self.seats.white.is_none() {
+      Some(Color::Black)
+    } else if self.seats.white.is_some() && self.seats.black.is_none() {
+      Some(Color::White)
+    } else {
+      None
+    };
+    self.finish_match(winner, "disconnect").into_iter().collect()
+  }
+
+  fn is_empty(&self) -> bool {
+    self.seats.black.is_none() && self.seats.white.is_none() && self.spectators.is_empty()
+  }
+
+  fn contains(&self, username: &str) -> bool {
+    self.seat_color(username).is_some() || self.spectators.iter().any(|u| u == username)
+  }
+
+  /// Whether the room appears in the lobby.
+  fn listed(&self) -> bool {
+    self.options.visibility == Visibility::Public
+  }
+
+  /// Tells lobby subscribers about this room's current listing.
+  fn publish_lobby(&self) {
+    if self.listed() {
+      let _ = self.lobby.send(LobbyEvent::Updated(self.lobby_entry()));
+    }
+  }
+
+  /// Owner-only check shared by the moderation actions, made under the same lock as the action
+  /// itself. Seated players can only be removed while no match is running.
+  fn check_moderation(&self, owner: &str, target: &str) -> Result<(), &'static str> {
+    if self.owner != owner {
+      return Err("forbidden");
+    }
+    if target == owner {
+      return Err("bad_request");
+    }
+    if self.seat_color(target).is_some() && matches!(self.state, RoomState::Playing) {
+      return Err("invalid_room_state");
+    }
+    Ok(())
+  }
+
+  /// Whether `username` may enter. Private rooms take either the password or the current invite
+  /// code, and each of `credentials` is tried as both; the owner can always come back. Banned
+  /// users are never let in.
+  fn admits(&self, username: &str, credentials: &[&str]) -> Result<(), &'static str> {
+    if self.banned.contains(username) {
+      return Err("banned");
+    }
+    if self.options.visibility != Visibility::Private || username == self.owner {
+      return Ok(());
+    }
+    let accepted = |credential: &&str| {
+      let password_ok = self
+        .password_hash
+        .as_ref()
+        .is_some_and(|h| *h == hash_room_password(self.room_id, credential));
+      let invite_ok = self
+        .invite_code
+        .as_deref()
+        .is_some_and(|code| code.eq_ignore_ascii_case(credential));
+      password_ok || invite_ok
+    };
+    if credentials.iter().filter(|c| !c.is_empty()).any(accepted) {
+      Ok(())
+    } else {
+      Err("room_locked")
+    }
+  }
+
+  /// Applies a rated result to both players' ratings. Players can only ready up for a rated match
+  /// with a known rating; should one be missing anyway the result goes unrated rather than
+  /// overwriting the stored rating with one derived from the default.
+  fn rate(&self, m: &Match, winner: Option<Color>) -> Option<(RatingChange, RatingChange)> {
+    let black = self.ratings.get(&m.black).map(|r| *r)?;
+    let white = self.ratings.get(&m.white).map(|r| *r)?;
+    let (black_after, white_after) = ratings::rate_game(black, white, winner);
+    self.ratings.insert(m.black.clone(), black_after);
+    self.ratings.insert(m.white.clone(), white_after);
+    Some((
+      RatingChange { before: black, after: black_after },
+      RatingChange { before: white, after: white_after },
+    ))
+  }
+
+  /// Hands a finished match to the history writer, if persistence is enabled.
+  fn record(
+    &self,
+    m: Match,
+    winner: Option<Color>,
+    reason: &str,
+    rating_changes: Option<(RatingChange, RatingChange)>,
+  ) {
+    let Some(sink) = &self.history else {
+      return;
+    };
+    let finished = FinishedMatch {
+      match_id: m.match_id,
+      room_id: self.room_id,
+      black: m.black,
+      white: m.white,
+      rule: self.options.rule,
+      forbidden_policy: self.options.forbidden_policy,
+      opening: self.options.opening,
+      board_size: self.options.board_size,
+      time_control: self.options.time_control,
+      rating_changes,
+      winner,
+      reason: reason.to_string(),
+      moves: m.moves,
+      started_at: m.started_at,
+      ended_at: Utc::now(),
+    };
+    if sink.send(finished).is_err() {
+      tracing::warn!(room_id = %self.room_id, "history: writer is gone, match not saved");
+    }
+  }
+
+  fn match_snapshot(&self) -> Option<MatchSnapshot> {
+    let m = self.current_match.as_ref()?;
+    Some(MatchSnapshot {
+      match_id: m.match_id.to_string(),
+      board_size: self.options.board_size,
+      rule: self.options.rule,
+      forbidden_policy: self.options.forbidden_policy,
+      opening: self.options.opening,
+      opening_phase: m.opening,
+      opening_restriction: self.options.opening.restriction(self.options.board_size),
+      time_control: self.options.time_control,
+      clocks: m.clock.as_ref().map(|c| c.view(Instant::now())),
+      turn: m.turn,
+      moves: m.moves.clone(),
+      draw_offer: m.draw_offer.as_deref().and_then(|u| self.seat_color(u)),
+      undo_request: m.undo_request.as_deref().and_then(|u| self.seat_color(u)),
+      hints_left: self.hints_allowed().then(|| HintsLeft {
+        black: self.hints_left(&m.black),
+        white: self.hints_left(&m.white),
+      }),
+    })
+  }
+
+  fn hints_allowed(&self) -> bool {
+    !self.options.rated && self.options.hints_per_game > 0
+  }
+
+  /// Hints `username` may still ask for in the current match.
+  fn hints_left(&self, username: &str) -> u32 {
+    let used = self
+      .current_match
+      .as_ref()
+      .and_then(|m| m.hints_used.get(username).copied())
+      .unwrap_or(0);
+    self.options.hints_per_game.saturating_sub(used)
+  }
+
+  /// `finish_match` followed by the refreshed `room.snapshot`, for callers that broadcast both.
+  fn finish_match_with_snapshot(&mut self, winner: Option<Color>, reason: &str) -> Vec<EnvelopeOut> {
+    let mut events: Vec<EnvelopeOut> = self.finish_match(winner, reason).into_iter().collect();
+    events.push(EnvelopeOut::event("room.snapshot", serde_json::to_value(self.snapshot()).unwrap()));
+    events
+  }
+
+  fn snapshot(&self) -> RoomSnapshot {
+    RoomSnapshot {
+      room_id: self.room_id.to_string(),
+      title: self.title.clone(),
+      owner: self.owner.clone(),
+      rule: self.options.rule,
+      forbidden_policy: self.options.forbidden_policy,
+      board_size: self.options.board_size,
+      opening: self.options.opening,
+      opening_restriction: self.options.opening.restriction(self.options.board_size),
+      allow_takeback: self.options.allow_takeback,
+      time_control: self.options.time_control,
+      rated: self.options.rated,
+      visibility: self.options.visibility,
+      has_password: self.password_hash.is_some(),
+      seats: SeatsSnapshot {
+        black: self.seats.black.as_ref().map(|s| SeatInfo {
+          username: s.username.clone(),
+          ready: s.ready,
+          connected: s.disconnected_until.is_none(),
+          rating: self.ratings.get(&s.username).map(|r| *r),
+        }),
+        white: self.seats.white.as_ref().map(|s| SeatInfo {
+          username: s.username.clone(),
+          ready: s.ready,
+          connected: s.disconnected_until.is_none(),
+          rating: self.ratings.get(&s.username).map(|r| *r),
+        }),
+      },
+      spectators: self.spectators.clone(),
+      state: self.state.clone(),
+      current_match: self.match_snapshot(),
+      series: self.series.clone(),
+      rematch_requested_by: self.rematch_request.clone(),
     }
   }
-  false
 }
@@ -0,0 +1,322 @@
+use serde::{Deserialize, Serialize};
+
+use crate::board::{opponent, Board, BLACK, EMPTY};
+
+/// Line directions: across, down and both diagonals.
+pub(crate) const DIRS: [(i32, i32); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+// Forbidden-point checks recurse through "is this three really a three?" lookups.
+// Real positions never need more than a few levels; the cap keeps pathological boards bounded.
+const MAX_FORBIDDEN_DEPTH: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleSet {
+  /// Five or more in a row wins.
+  #[default]
+  Freestyle,
+  /// Exactly five wins; overlines don't count for either side.
+  Standard,
+  /// Five or more wins unless the line is blocked by opponent stones at both ends.
+  Caro,
+  /// Black needs exactly five and may not play forbidden points; White wins with five or more.
+  Renju,
+}
+
+impl RuleSet {
+  pub fn win_check(self) -> &'static dyn WinCheck {
+    match self {
+      RuleSet::Freestyle => &FreestyleWin,
+      RuleSet::Standard => &StandardWin,
+      RuleSet::Caro => &CaroWin,
+      RuleSet::Renju => &RenjuWin,
+    }
+  }
+
+  /// Whether `v` may play the empty point (r, c). Only Renju forbids points, and only to Black.
+  pub fn allows(self, board: &Board, r: usize, c: usize, v: u8) -> bool {
+    !(self == RuleSet::Renju && v == BLACK && renju_forbidden(board, r, c).is_some())
+  }
+
+  /// Whether `v` on the empty point (r, c) would win. The point is left empty again; legality is
+  /// not checked, see [`RuleSet::allows`].
+  pub fn wins_at(self, board: &mut Board, r: usize, c: usize, v: u8) -> bool {
+    board[r][c] = v;
+    let won = self.win_check().is_win(board, r, c, v);
+    board[r][c] = EMPTY;
+    won
+  }
+
+  /// [`RuleSet::wins_at`] for a move `v` is allowed to play.
+  pub fn wins_legally_at(self, board: &mut Board, r: usize, c: usize, v: u8) -> bool {
+    // The forbidden-point check is far dearer than the win check, so it goes last.
+    self.wins_at(board, r, c, v) && self.allows(board, r, c, v)
+  }
+
+  /// Whether the stone of `v` at (r, c) leaves two points along `dir` where `v` legally wins, i.e.
+  /// an open four on that line. Whether (r, c) itself was a legal move is up to the caller.
+  pub fn open_four_along(self, board: &mut Board, r: usize, c: usize, dir: (i32, i32), v: u8) -> bool {
+    line_points(board, r, c, dir, v, 4)
+      .into_iter()
+      .filter(|&(pr, pc)| self.wins_legally_at(board, pr, pc, v))
+      .count()
+      >= 2
+  }
+}
+
+/// Decides whether the stone `v` just placed at (r, c) ends the game in a win.
+pub trait WinCheck: Sync {
+  fn is_win(&self, board: &Board, r: usize, c: usize, v: u8) -> bool;
+}
+
+pub struct FreestyleWin;
+pub struct StandardWin;
+pub struct CaroWin;
+pub struct RenjuWin;
+
+impl WinCheck for FreestyleWin {
+  fn is_win(&self, board: &Board, r: usize, c: usize, v: u8) -> bool {
+    is_five(board, r, c, v, false)
+  }
+}
+
+impl WinCheck for StandardWin {
+  fn is_win(&self, board: &Board, r: usize, c: usize, v: u8) -> bool {
+    is_five(board, r, c, v, true)
+  }
+}
+
+impl WinCheck for CaroWin {
+  fn is_win(&self, board: &Board, r: usize, c: usize, v: u8) -> bool {
+    let opponent = opponent(v);
+    DIRS.iter().any(|&(dr, dc)| {
+      let (before, after) = run_extent(board, r, c, (dr, dc), v);
+      if before + after + 1 < 5 {
+        return false;
+      }
+      // The board edge doesn't count as a block, only opponent stones do.
+      let head = at(board, r as i32 + dr * (after as i32 + 1), c as i32 + dc * (after as i32 + 1));
+      let tail = at(board, r as i32 - dr * (before as i32 + 1), c as i32 - dc * (before as i32 + 1));
+      !(head == Some(opponent) && tail == Some(opponent))
+    })
+  }
+}
+
+impl WinCheck for RenjuWin {
+  fn is_win(&self, board: &Board, r: usize, c: usize, v: u8) -> bool {
+    is_five(board, r, c, v, v == BLACK)
+  }
+}
+
+/// What happens when Black plays onto a Renju forbidden point.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForbiddenPolicy {
+  /// The move is refused and Black must play elsewhere.
+  #[default]
+  Reject,
+  /// The move stands and Black loses the game.
+  Lose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forbidden {
+  DoubleThree,
+  DoubleFour,
+  Overline,
+}
+
+impl Forbidden {
+  pub fn reason(self) -> &'static str {
+    match self {
+      Forbidden::DoubleThree => "forbidden_33",
+      Forbidden::DoubleFour => "forbidden_44",
+      Forbidden::Overline => "forbidden_overline",
+    }
+  }
+}
+
+fn at(board: &Board, r: i32, c: i32) -> Option<u8> {
+  board.get(r, c)
+}
+
+/// Stones of `v` directly behind and ahead of (r, c) along direction (dr, dc), excluding (r, c).
+fn run_extent(board: &Board, r: usize, c: usize, (dr, dc): (i32, i32), v: u8) -> (usize, usize) {
+  let mut counts = [0usize; 2];
+  for (i, sign) in [-1, 1].into_iter().enumerate() {
+    let mut rr = r as i32 + dr * sign;
+    let mut cc = c as i32 + dc * sign;
+    while at(board, rr, cc) == Some(v) {
+      counts[i] += 1;
+      rr += dr * sign;
+      cc += dc * sign;
+    }
+  }
+  (counts[0], counts[1])
+}
+
+/// Empty points in the five-cell windows along `(dr, dc)` through (r, c) that hold exactly `stones`
+/// stones of `v` and none of the opponent's. Every point that can extend the line through (r, c)
+/// into a five, four or three of `v` is among them.
+pub(crate) fn line_points(
+  board: &Board,
+  r: usize,
+  c: usize,
+  (dr, dc): (i32, i32),
+  v: u8,
+  stones: usize,
+) -> Vec<(usize, usize)> {
+  let mut out = vec![];
+  for start in -4..=0 {
+    let cells: Vec<(i32, i32)> = (start..start + 5)
+      .map(|k| (r as i32 + dr * k, c as i32 + dc * k))
+      .collect();
+    let Some(values) = cells.iter().map(|&(rr, cc)| at(board, rr, cc)).collect::<Option<Vec<u8>>>() else {
+      continue;
+    };
+    let own = values.iter().filter(|&&x| x == v).count();
+    let empty = values.iter().filter(|&&x| x == EMPTY).count();
+    if own != stones || own + empty != 5 {
+      continue;
+    }
+    for (&(rr, cc), &x) in cells.iter().zip(&values) {
+      let q = (rr as usize, cc as usize);
+      if x == EMPTY && !out.contains(&q) {
+        out.push(q);
+      }
+    }
+  }
+  out
+}
+
+/// Length of the unbroken run of `v` through (r, c) along direction (dr, dc).
+pub fn run_length(board: &Board, r: usize, c: usize, d: (i32, i32), v: u8) -> usize {
+  let (before, after) = run_extent(board, r, c, d, v);
+  before + after + 1
+}
+
+/// Whether the stone at (r, c) is part of a five. With `exact`, runs of six or more don't count.
+pub fn is_five(board: &Board, r: usize, c: usize, v: u8, exact: bool) -> bool {
+  DIRS.iter().any(|&d| {
+    let n = run_length(board, r, c, d, v);
+    if exact { n == 5 } else { n >= 5 }
+  })
+}
+
+/// Empty points on the line through (r, c) that would turn the run containing (r, c) into a five.
+fn five_points(
+  board: &mut Board,
+  r: usize,
+  c: usize,
+  (dr, dc): (i32, i32),
+  v: u8,
+  exact: bool,
+) -> Vec<i32> {
+  let mut out = vec![];
+  for k in -4..=4 {
+    if k == 0 {
+      continue;
+    }
+    let rr = r as i32 + dr * k;
+    let cc = c as i32 + dc * k;
+    if at(board, rr, cc) != Some(EMPTY) {
+      continue;
+    }
+    board[rr as usize][cc as usize] = v;
+    let n = run_length(board, r, c, (dr, dc), v);
+    board[rr as usize][cc as usize] = EMPTY;
+    if (exact && n == 5) || (!exact && n >= 5) {
+      out.push(k);
+    }
+  }
+  out
+}
+
+/// Number of fours the stone at (r, c) takes part in along one direction.
+///
+/// A straight four (`.XXXX.`) has two completion points but counts once; two completion points
+/// any other distance apart (`X.XXX.X`, `XX.XX.XX`) are two separate fours on the same line.
+fn fours_in_dir(board: &mut Board, r: usize, c: usize, d: (i32, i32), v: u8, exact: bool) -> usize {
+  let pts = five_points(board, r, c, d, v, exact);
+  if pts.len() == 2 && (pts[1] - pts[0]) == 5 {
+    1
+  } else {
+    pts.len()
+  }
+}
+
+fn is_straight_four(board: &mut Board, r: usize, c: usize, d: (i32, i32), v: u8) -> bool {
+  let pts = five_points(board, r, c, d, v, true);
+  pts.len() == 2 && (pts[1] - pts[0]) == 5
+}
+
+/// Whether Black's stone at (r, c) forms a real three along `d`: some empty point on the line turns
+/// it into a straight four, and that point is not itself forbidden.
+fn is_three_in_dir(board: &mut Board, r: usize, c: usize, (dr, dc): (i32, i32), depth: u32) -> bool {
+  for k in -4..=4 {
+    if k == 0 {
+      continue;
+    }
+    let rr = r as i32 + dr * k;
+    let cc = c as i32 + dc * k;
+    if at(board, rr, cc) != Some(EMPTY) {
+      continue;
+    }
+    let (qr, qc) = (rr as usize, cc as usize);
+    board[qr][qc] = BLACK;
+    let straight = is_straight_four(board, r, c, (dr, dc), BLACK);
+    board[qr][qc] = EMPTY;
+    if straight && (depth >= MAX_FORBIDDEN_DEPTH || forbidden_at(board, qr, qc, depth + 1).is_none()) {
+      return true;
+    }
+  }
+  false
+}
+
+fn forbidden_at(board: &mut Board, r: usize, c: usize, depth: u32) -> Option<Forbidden> {
+  board[r][c] = BLACK;
+  let verdict = classify_black(board, r, c, depth);
+  board[r][c] = EMPTY;
+  verdict
+}
+
+fn classify_black(board: &mut Board, r: usize, c: usize, depth: u32) -> Option<Forbidden> {
+  // An exact five wins outright, even if the same stone also makes a double-four or double-three.
+  if is_five(board, r, c, BLACK, true) {
+    return None;
+  }
+  if DIRS.iter().any(|&d| run_length(board, r, c, d, BLACK) >= 6) {
+    return Some(Forbidden::Overline);
+  }
+
+  let mut fours = 0;
+  let mut four_dirs = [false; 4];
+  for (i, &d) in DIRS.iter().enumerate() {
+    let n = fours_in_dir(board, r, c, d, BLACK, true);
+    fours += n;
+    four_dirs[i] = n > 0;
+  }
+  if fours >= 2 {
+    return Some(Forbidden::DoubleFour);
+  }
+
+  let mut threes = 0;
+  for (i, &d) in DIRS.iter().enumerate() {
+    if !four_dirs[i] && is_three_in_dir(board, r, c, d, depth) {
+      threes += 1;
+    }
+  }
+  if threes >= 2 {
+    return Some(Forbidden::DoubleThree);
+  }
+  None
+}
+
+/// Renju forbidden-point check for a Black stone about to be placed on the empty point (r, c).
+pub fn renju_forbidden(board: &Board, r: usize, c: usize) -> Option<Forbidden> {
+  if board[r][c] != EMPTY {
+    return None;
+  }
+  let mut scratch = board.clone();
+  forbidden_at(&mut scratch, r, c, 0)
+}
@@ -0,0 +1,436 @@
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  board::{opponent, stone, Board, EMPTY},
+  rooms::{Color, Coord},
+  rules::{self, RuleSet, DIRS},
+};
+
+/// Upper bounds on any search, so no caller can tie up a worker for long.
+pub const MAX_DEPTH: u32 = 30;
+pub const MAX_NODES: u64 = 500_000;
+pub const MAX_TIME: Duration = Duration::from_secs(10);
+
+/// Budget for searches requested over the public API. Every node scans the board and may run
+/// Renju forbidden-point checks, so these stay far below the internal caps.
+pub const REQUEST_MAX_NODES: u64 = 20_000;
+pub const REQUEST_MAX_TIME: Duration = Duration::from_secs(2);
+
+/// Nodes between two looks at the clock.
+const CLOCK_INTERVAL: u64 = 64;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+  /// Victory by Continuous Fours: every attacking move makes a four.
+  #[default]
+  Vcf,
+  /// Victory by Continuous Threats: attacking moves may also be threes.
+  Vct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+  /// Attacking moves allowed before the winning five.
+  pub max_depth: u32,
+  /// Positions the search may visit before giving up.
+  pub max_nodes: u64,
+  /// Wall-clock time the search may take before giving up.
+  pub max_time: Duration,
+}
+
+impl Limits {
+  pub fn for_kind(kind: Kind) -> Self {
+    match kind {
+      Kind::Vcf => Self {
+        max_depth: 20,
+        max_nodes: 100_000,
+        max_time: Duration::from_secs(5),
+      },
+      // Threes widen the tree a lot; keep the default horizon short.
+      Kind::Vct => Self {
+        max_depth: 5,
+        max_nodes: 50_000,
+        max_time: Duration::from_secs(5),
+      },
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+  /// A forced win was found; `line` holds it.
+  Win,
+  /// The whole tree within `max_depth` was searched and there is no forced win.
+  NoWin,
+  /// The node or time budget ran out first.
+  Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Solution {
+  pub kind: Kind,
+  pub attacker: Color,
+  pub verdict: Verdict,
+  /// Moves alternating from the attacker, ending with the five. Against the main line the
+  /// defender is given the reply that holds out longest. Empty unless `verdict` is `Win`.
+  pub line: Vec<Coord>,
+  pub nodes: u64,
+}
+
+/// Searches for a `kind` win for `attacker`, who is to move on `board`. A Black attacker never
+/// plays Renju forbidden points, and a Black defender whose only block is forbidden has lost.
+/// The search deepens one threat at a time, so the line found is among the shortest.
+/// CPU-bound; run it off the async executor.
+pub fn solve(board: &Board, attacker: Color, rule: RuleSet, kind: Kind, limits: Limits) -> Solution {
+  let mut solver = Solver::new(board, attacker, rule, kind, limits);
+  let mut line = None;
+  for depth in 0..=limits.max_depth.min(MAX_DEPTH) {
+    solver.cut = false;
+    line = solver.attack(depth);
+    // Stop once won, out of nodes, or when no branch was cut short by the depth.
+    if line.is_some() || solver.aborted || !solver.cut {
+      break;
+    }
+  }
+  let verdict = match &line {
+    Some(_) => Verdict::Win,
+    None if solver.aborted => Verdict::Unknown,
+    None => Verdict::NoWin,
+  };
+  Solution {
+    kind,
+    attacker,
+    verdict,
+    line: line
+      .unwrap_or_default()
+      .into_iter()
+      .map(|(r, c)| Coord {
+        row: r as i32,
+        col: c as i32,
+      })
+      .collect(),
+    nodes: solver.nodes,
+  }
+}
+
+type Point = (usize, usize);
+
+struct Solver {
+  board: Board,
+  rule: RuleSet,
+  kind: Kind,
+  attacker: u8,
+  defender: u8,
+  nodes: u64,
+  node_limit: u64,
+  deadline: Instant,
+  aborted: bool,
+  /// Some branch of the current iteration stopped at the depth limit.
+  cut: bool,
+  /// Zobrist hash of `board`.
+  hash: u64,
+  /// Deepest search that failed from each position with the attacker to move.
+  failed: HashMap<u64, u32>,
+}
+
+/// Zobrist key for stone `v` on cell `idx`, derived with splitmix64 so no table is needed.
+fn zobrist(idx: usize, v: u8) -> u64 {
+  let mut z = (idx as u64 * 2 + v as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z ^ (z >> 31)
+}
+
+impl Solver {
+  fn new(board: &Board, attacker: Color, rule: RuleSet, kind: Kind, limits: Limits) -> Self {
+    let attacker = stone(attacker);
+    let defender = opponent(attacker);
+    let size = board.size();
+    let mut hash = 0;
+    for r in 0..size {
+      for c in 0..size {
+        if board[r][c] != EMPTY {
+          hash ^= zobrist(r * size + c, board[r][c]);
+        }
+      }
+    }
+    Self {
+      board: board.clone(),
+      rule,
+      kind,
+      attacker,
+      defender,
+      nodes: 0,
+      node_limit: limits.max_nodes.min(MAX_NODES),
+      deadline: Instant::now() + limits.max_time.min(MAX_TIME),
+      aborted: false,
+      cut: false,
+      hash,
+      failed: HashMap::new(),
+    }
+  }
+
+  /// Counts a node; `false` once the node or time budget is spent.
+  fn tick(&mut self) -> bool {
+    self.nodes += 1;
+    if self.nodes > self.node_limit
+      || (self.nodes % CLOCK_INTERVAL == 1 && Instant::now() >= self.deadline)
+    {
+      self.aborted = true;
+    }
+    !self.aborted
+  }
+
+  fn play(&mut self, (r, c): Point, v: u8) {
+    self.board[r][c] = v;
+    self.hash ^= zobrist(r * self.board.size() + c, v);
+  }
+
+  fn unplay(&mut self, (r, c): Point) {
+    self.hash ^= zobrist(r * self.board.size() + c, self.board[r][c]);
+    self.board[r][c] = EMPTY;
+  }
+
+  fn is_legal(&self, (r, c): Point, v: u8) -> bool {
+    self.rule.allows(&self.board, r, c, v)
+  }
+
+  fn wins_at(&mut self, (r, c): Point, v: u8) -> bool {
+    self.rule.wins_legally_at(&mut self.board, r, c, v)
+  }
+
+  /// [`rules::line_points`] over the whole board: empty points in any five-cell window holding
+  /// exactly `stones` of `v` and nothing of the opponent.
+  fn window_points(&self, v: u8, stones: usize) -> Vec<Point> {
+    let size = self.board.size() as i32;
+    let mut seen = vec![false; (size * size) as usize];
+    let mut out = vec![];
+    for r in 0..size {
+      for c in 0..size {
+        for (dr, dc) in DIRS {
+          let end = (r + dr * 4, c + dc * 4);
+          if end.0 < 0 || end.0 >= size || end.1 < 0 || end.1 >= size {
+            continue;
+          }
+          let cells = (0..5).map(|k| (r + dr * k, c + dc * k));
+          let (mut own, mut empty) = (0, 0);
+          for (rr, cc) in cells.clone() {
+            match self.board[rr as usize][cc as usize] {
+              x if x == v => own += 1,
+              EMPTY => empty += 1,
+              _ => {}
+            }
+          }
+          if own != stones || own + empty != 5 {
+            continue;
+          }
+          for (rr, cc) in cells {
+            let i = (rr * size + cc) as usize;
+            if self.board[rr as usize][cc as usize] == EMPTY && !seen[i] {
+              seen[i] = true;
+              out.push((rr as usize, cc as usize));
+            }
+          }
+        }
+      }
+    }
+    out
+  }
+
+  /// Points where `v` makes five right away.
+  fn win_points(&mut self, v: u8) -> Vec<Point> {
+    self
+      .window_points(v, 4)
+      .into_iter()
+      .filter(|&p| self.wins_at(p, v))
+      .collect()
+  }
+
+  /// Whether the stone just placed at `p` gives `v` a point to make five.
+  fn made_four(&mut self, p: Point, v: u8) -> bool {
+    DIRS.iter().any(|&dir| {
+      rules::line_points(&self.board, p.0, p.1, dir, v, 4)
+        .into_iter()
+        .any(|q| self.wins_at(q, v))
+    })
+  }
+
+  /// Whether `v` playing the empty point `q` leaves two points to make five along `dir`.
+  fn makes_open_four_along(&mut self, q: Point, v: u8, dir: (i32, i32)) -> bool {
+    self.board[q.0][q.1] = v;
+    let open = self.rule.open_four_along(&mut self.board, q.0, q.1, dir, v);
+    self.board[q.0][q.1] = EMPTY;
+    // The forbidden-point check is far dearer than the rest, so it goes last.
+    open && self.is_legal(q, v)
+  }
+
+  /// Whether `v` playing the empty point `q` makes an open four.
+  fn makes_open_four(&mut self, q: Point, v: u8) -> bool {
+    DIRS.iter().any(|&dir| self.makes_open_four_along(q, v, dir))
+  }
+
+  /// Whether the stone just placed at `p` gives `v` a point to make an open four on one of its
+  /// lines.
+  fn made_three(&mut self, p: Point, v: u8) -> bool {
+    DIRS.iter().any(|&dir| {
+      rules::line_points(&self.board, p.0, p.1, dir, v, 3)
+        .into_iter()
+        .any(|q| self.makes_open_four_along(q, v, dir))
+    })
+  }
+
+  /// Attacking moves worth trying: fours first, then (for VCT) threes.
+  fn threats(&mut self) -> Vec<Point> {
+    let v = self.attacker;
+    let mut out = vec![];
+    for p in self.window_points(v, 3) {
+      self.board[p.0][p.1] = v;
+      let four = self.made_four(p, v);
+      self.board[p.0][p.1] = EMPTY;
+      if four && self.is_legal(p, v) {
+        out.push(p);
+      }
+    }
+    if self.kind == Kind::Vct {
+      for p in self.window_points(v, 2) {
+        if out.contains(&p) {
+          continue;
+        }
+        self.board[p.0][p.1] = v;
+        let three = self.made_three(p, v);
+        self.board[p.0][p.1] = EMPTY;
+        if three && self.is_legal(p, v) {
+          out.push(p);
+        }
+      }
+    }
+    out
+  }
+
+  /// Attacker to move. Returns the winning line, or `None` if there is none within `depth`
+  /// further threats.
+  fn attack(&mut self, depth: u32) -> Option<Vec<Point>> {
+    if !self.tick() {
+      return None;
+    }
+    let (a, d) = (self.attacker, self.defender);
+    if let Some(&p) = self.win_points(a).first() {
+      return Some(vec![p]);
+    }
+    let blocks = self.win_points(d);
+    if blocks.len() > 1 {
+      return None;
+    }
+    if depth == 0 {
+      self.cut = true;
+      return None;
+    }
+    if self.failed.get(&self.hash).is_some_and(|&searched| searched >= depth) {
+      return None;
+    }
+
+    // A defender's four has to be blocked, and the block only keeps the initiative if it leaves a
+    // threat standing; `respond` checks that.
+    let moves = match blocks.first() {
+      Some(&p) if self.is_legal(p, a) => vec![p],
+      Some(_) => vec![],
+      None => self.threats(),
+    };
+    for m in moves {
+      self.play(m, a);
+      let line = self.respond(depth - 1);
+      self.unplay(m);
+      if let Some(mut line) = line {
+        line.insert(0, m);
+        return Some(line);
+      }
+      if self.aborted {
+        return None;
+      }
+    }
+    self.failed.insert(self.hash, depth);
+    None
+  }
+
+  /// Defender to move after an attacking move. The attack holds only if every reasonable defence
+  /// loses.
+  fn respond(&mut self, depth: u32) -> Option<Vec<Point>> {
+    if !self.tick() {
+      return None;
+    }
+    let (a, d) = (self.attacker, self.defender);
+    if !self.win_points(d).is_empty() {
+      return None;
+    }
+    let fives = self.win_points(a);
+    let defences = if !fives.is_empty() {
+      fives
+    } else if self.kind == Kind::Vct {
+      self.three_defences()?
+    } else {
+      return None;
+    };
+
+    let mut longest: Option<Vec<Point>> = None;
+    for p in defences {
+      if !self.is_legal(p, d) {
+        continue;
+      }
+      self.play(p, d);
+      let line = self.attack(depth);
+      self.unplay(p);
+      let mut line = line?;
+      line.insert(0, p);
+      if longest.as_ref().is_none_or(|l| line.len() > l.len()) {
+        longest = Some(line);
+      }
+    }
+    // No legal defence at all: a Black defender blocked only by forbidden points.
+    Some(longest.unwrap_or_default())
+  }
+
+  /// Replies to a three: the points that make the attacker's open four, the points that would
+  /// complete it, and the defender's own fours. `None` if the attacker has no three.
+  fn three_defences(&mut self) -> Option<Vec<Point>> {
+    let (a, d) = (self.attacker, self.defender);
+    let opens: Vec<Point> = self
+      .window_points(a, 3)
+      .into_iter()
+      .filter(|&q| self.makes_open_four(q, a))
+      .collect();
+    if opens.is_empty() {
+      return None;
+    }
+    let mut out = opens.clone();
+    for q in opens {
+      self.board[q.0][q.1] = a;
+      for dir in DIRS {
+        for p in rules::line_points(&self.board, q.0, q.1, dir, a, 4) {
+          if !out.contains(&p) && self.wins_at(p, a) {
+            out.push(p);
+          }
+        }
+      }
+      self.board[q.0][q.1] = EMPTY;
+    }
+    for p in self.window_points(d, 3) {
+      if out.contains(&p) {
+        continue;
+      }
+      self.board[p.0][p.1] = d;
+      let four = self.made_four(p, d);
+      self.board[p.0][p.1] = EMPTY;
+      if four {
+        out.push(p);
+      }
+    }
+    Some(out)
+  }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+
+use anyhow::{Context, anyhow};
+use tokio::sync::mpsc;
+
+use crate::{
+  bots::{self, Engine, Position},
+  history::FinishedMatch,
+  opening::OpeningPhase,
+  rooms::{Color, RoomOptions, RoomService, SeatKind},
+};
+
+/// Scores this close to 0 or 1 are clamped so perfect results still give a finite Elo.
+const SCORE_CLAMP: f64 = 0.001;
+
+/// z-value of the 95% confidence interval used for error bars.
+const Z_95: f64 = 1.96;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// Everyone plays everyone.
+  RoundRobin,
+  /// The first entrant plays everyone else; the others don't meet.
+  Gauntlet,
+}
+
+impl std::str::FromStr for Format {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "round-robin" => Ok(Format::RoundRobin),
+      "gauntlet" => Ok(Format::Gauntlet),
+      _ => Err(()),
+    }
+  }
+}
+
+/// One game of the schedule, by entrant index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pairing {
+  pub black: usize,
+  pub white: usize,
+}
+
+/// Every game of the tournament in playing order. Each pair meets `games_per_pair` times, taking
+/// turns with Black.
+pub fn schedule(format: Format, entrants: usize, games_per_pair: u32) -> Vec<Pairing> {
+  let pairs: Vec<(usize, usize)> = match format {
+    Format::RoundRobin => (0..entrants)
+      .flat_map(|a| (a + 1..entrants).map(move |b| (a, b)))
+      .collect(),
+    Format::Gauntlet => (1..entrants).map(|b| (0, b)).collect(),
+  };
+  let mut games = vec![];
+  for round in 0..games_per_pair {
+    for &(a, b) in &pairs {
+      let (black, white) = if round % 2 == 0 { (a, b) } else { (b, a) };
+      games.push(Pairing { black, white });
+    }
+  }
+  games
+}
+
+/// An engine taking part, under a name that is unique within the tournament.
+pub struct Entrant {
+  pub name: String,
+  pub engine: Box<dyn Engine>,
+}
+
+/// Plays one game between two entrants through the regular room logic and returns the finished
+/// match as handed to the history sink `finished` of `rooms`.
+pub async fn play_game(
+  rooms: &RoomService,
+  finished: &mut mpsc::UnboundedReceiver<FinishedMatch>,
+  options: &RoomOptions,
+  black: &mut Entrant,
+  white: &mut Entrant,
+) -> anyhow::Result<FinishedMatch> {
+  let (room_id, _) = rooms
+    .create_room_with_options(&black.name, "tournament".to_string(), options.clone())
+    .await
+    .map_err(|e| anyhow!("create room: {e}"))?;
+  rooms.join_room(&white.name, room_id).await.map_err(|e| anyhow!("join room: {e}"))?;
+  rooms
+    .take_seat(&white.name, SeatKind::White)
+    .await
+    .map_err(|e| anyhow!("take seat: {e}"))?;
+  for name in [&black.name, &white.name] {
+    rooms.set_ready(name, true).await.map_err(|e| anyhow!("ready: {e}"))?;
+  }
+
+  while let Some(snapshot) = rooms.snapshot(room_id).await {
+    let Some(m) = &snapshot.current_match else { break };
+    let actor = m.opening_phase.map(OpeningPhase::actor).unwrap_or(m.turn);
+    // Swap2 may have traded seats, so find the mover by seat rather than by starting color.
+    let seat = match actor {
+      Color::Black => &snapshot.seats.black,
+      Color::White => &snapshot.seats.white,
+    };
+    let mover = seat.as_ref().map(|s| s.username.as_str()).context("empty seat mid-game")?;
+    let entrant = if mover == black.name { &mut *black } else { &mut *white };
+    let position = Position::from_match(m);
+
+    if m.opening_phase.is_some_and(OpeningPhase::is_choice) {
+      let choice = bots::swap_choice(&position.board(), m.opening_phase);
+      rooms
+        .match_opening_choice(&entrant.name, choice)
+        .await
+        .map_err(|(code, _)| anyhow!("opening choice: {code}"))?;
+      continue;
+    }
+
+    let accepted = match entrant.engine.think(&position).await {
+      Ok(coord) => {
+        let (_, payload, _) = rooms
+          .match_move(&entrant.name, coord)
+          .await
+          .map_err(|(code, _)| anyhow!("move: {code}"))?;
+        payload.get("accepted").and_then(|v| v.as_bool()) == Some(true)
+      }
+      Err(e) => {
+        tracing::warn!(engine = %entrant.name, error = %e, "engine failed");
+        false
+      }
+    };
+    if !accepted {
+      // A failed or illegal reply forfeits the game, unless the move already ended it.
+      let _ = rooms.match_resign(&entrant.name).await;
+    }
+  }
+
+  for name in [&black.name, &white.name] {
+    rooms.leave_room(name).await;
+  }
+  finished.recv().await.context("history sink closed")
+}
+
+/// Wins, draws and losses from one side's point of view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+  pub wins: u32,
+  pub draws: u32,
+  pub losses: u32,
+}
+
+impl Tally {
+  pub fn games(&self) -> u32 {
+    self.wins + self.draws + self.losses
+  }
+
+  pub fn points(&self) -> f64 {
+    self.wins as f64 + self.draws as f64 / 2.0
+  }
+
+  fn add(&mut self, other: Tally) {
+    self.wins += other.wins;
+    self.draws += other.draws;
+    self.losses += other.losses;
+  }
+}
+
+/// Elo difference to the opposition with its 95% error bar, in Elo points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EloEstimate {
+  pub elo: f64,
+  pub error: f64,
+}
+
+fn elo_for_score(score: f64) -> f64 {
+  let s = score.clamp(SCORE_CLAMP, 1.0 - SCORE_CLAMP);
+  400.0 * (s / (1.0 - s)).log10()
+}
+
+/// Elo estimate from a set of results, with the error bar taken from the per-game score variance.
+pub fn estimate_elo(t: Tally) -> Option<EloEstimate> {
+  let n = t.games() as f64;
+  if n == 0.0 {
+    return None;
+  }
+  let s = t.points() / n;
+  let variance = (t.wins as f64 * (1.0 - s).powi(2)
+    + t.draws as f64 * (0.5 - s).powi(2)
+    + t.losses as f64 * s.powi(2))
+    / n;
+  let margin = Z_95 * (variance / n).sqrt();
+  Some(EloEstimate {
+    elo: elo_for_score(s),
+    error: (elo_for_score(s + margin) - elo_for_score(s - margin)) / 2.0,
+  })
+}
+
+/// Head-to-head results of every entrant against every other.
+#[derive(Debug, Clone)]
+pub struct Crosstable {
+  names: Vec<String>,
+  // results[a][b] is a's record against b.
+  results: Vec<Vec<Tally>>,
+}
+
+impl Crosstable {
+  pub fn new(names: Vec<String>) -> Self {
+    let n = names.len();
+    Self {
+      names,
+      results: vec![vec![Tally::default(); n]; n],
+    }
+  }
+
+  /// Records a game between entrants `black` and `white`.
+  pub fn record(&mut self, black: usize, white: usize, winner: Option<Color>) {
+    match winner {
+      Some(Color::Black) => {
+        self.results[black][white].wins += 1;
+        self.results[white][black].losses += 1;
+      }
+      Some(Color::White) => {
+        self.results[white][black].wins += 1;
+        self.results[black][white].losses += 1;
+      }
+      None => {
+        self.results[black][white].draws += 1;
+        self.results[white][black].draws += 1;
+      }
+    }
+  }
+
+  pub fn head_to_head(&self, a: usize, b: usize) -> Tally {
+    self.results[a][b]
+  }
+
+  /// `a`'s combined record against everyone.
+  pub fn total(&self, a: usize) -> Tally {
+    let mut total = Tally::default();
+    for t in &self.results[a] {
+      total.add(*t);
+    }
+    total
+  }
+}
+
+impl fmt::Display for Crosstable {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let width = self.names.iter().map(String::len).max().unwrap_or(0).max(4);
+    let mut order: Vec<usize> = (0..self.names.len()).collect();
+    order.sort_by(|&a, &b| self.total(b).points().total_cmp(&self.total(a).points()));
+
+    write!(f, "{:>3}  {:<width$}", "#", "Name")?;
+    for (col, _) in order.iter().enumerate() {
+      write!(f, " {:>8}", col + 1)?;
+    }
+    writeln!(f, " {:>7} {:>6} {:>13}", "Points", "Games", "Elo")?;
+
+    for (rank, &a) in order.iter().enumerate() {
+      write!(f, "{:>3}  {:<width$}", rank + 1, self.names[a])?;
+      for &b in &order {
+        if a == b {
+          write!(f, " {:>8}", "-")?;
+        } else {
+          let t = self.results[a][b];
+          write!(f, " {:>8}", format!("{}-{}-{}", t.wins, t.draws, t.losses))?;
+        }
+      }
+      let total = self.total(a);
+      let elo = match estimate_elo(total) {
+        Some(e) => format!("{:+.0} ± {:.0}", e.elo, e.error),
+        None => "-".to_string(),
+      };
+      writeln!(f, " {:>7.1} {:>6} {:>13}", total.points(), total.games(), elo)?;
+    }
+    Ok(())
+  }
+}
+
+/// Point name in the usual `h8` style: files from `a` on the left, ranks from 1 at the bottom.
+pub fn coord_name(board_size: usize, row: i32, col: i32) -> String {
+  let file = (b'a' + col as u8) as char;
+  format!("{file}{}", board_size as i32 - row)
+}
+
+/// One game in PGN-style renju notation.
+pub fn game_notation(round: usize, game: &FinishedMatch) -> String {
+  let result = match game.winner {
+    Some(Color::Black) => "1-0",
+    Some(Color::White) => "0-1",
+    None => "1/2-1/2",
+  };
+  let rule = serde_json::to_value(game.rule).ok();
+  let rule = rule.as_ref().and_then(|v| v.as_str()).unwrap_or_default();
+
+  let mut out = String::new();
+  out.push_str("[Event \"Engine tournament\"]\n");
+  out.push_str(&format!("[Round \"{round}\"]\n"));
+  out.push_str(&format!("[Date \"{}\"]\n", game.started_at.format("%Y.%m.%d")));
+  out.push_str(&format!("[Black \"{}\"]\n", game.black));
+  out.push_str(&format!("[White \"{}\"]\n", game.white));
+  out.push_str(&format!("[Result \"{result}\"]\n"));
+  out.push_str(&format!("[Rule \"{rule}\"]\n"));
+  out.push_str(&format!("[BoardSize \"{}\"]\n", game.board_size));
+  out.push_str(&format!("[Termination \"{}\"]\n\n", game.reason));
+
+  for (i, pair) in game.moves.chunks(2).enumerate() {
+    out.push_str(&format!("{}.", i + 1));
+    for mv in pair {
+      out.push(' ');
+      out.push_str(&coord_name(game.board_size, mv.coord.row, mv.coord.col));
+    }
+    out.push(' ');
+  }
+  out.push_str(result);
+  out.push_str("\n\n");
+  out
+}
@@ -5,20 +5,28 @@ use axum::{
     },
     response::IntoResponse,
 };
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use uuid::Uuid;
 
 use crate::{
+  ai::Difficulty,
+  analysis,
+  api::AppState,
   auth,
-  config::Config,
+  bots::{self, Position},
+  chat::ChatChannel,
+  matchmaking::Matchmaker,
+  opening::OpeningChoice,
+  piskvork::{EngineSpec, PiskvorkEngine},
   protocol::{EnvelopeIn, EnvelopeOut},
-  rooms::{Coord, RoomService, SeatKind},
+  ratings,
+  rooms::{Coord, RoomOptions, RoomService, SeatKind},
 };
 
-async fn broadcast_room_event(hub: &Hub, rooms: &RoomService, room_id: Uuid, evt: &EnvelopeOut) {
+pub(crate) async fn broadcast_room_event(hub: &Hub, rooms: &RoomService, room_id: Uuid, evt: &EnvelopeOut) {
   for u in rooms.participants(room_id).await {
     hub.send_json(&u, evt);
   }
@@ -34,7 +42,29 @@ async fn broadcast_room_snapshot(
   broadcast_room_event(hub, rooms, room_id, &evt).await;
 }
 
-async fn leave_room_with_broadcast(
+/// Replies to `req` and fans the resulting events out to everyone in the room.
+async fn reply_and_broadcast(
+  hub: &Hub,
+  rooms: &RoomService,
+  username: &str,
+  req: &EnvelopeIn,
+  res: Result<(Uuid, serde_json::Value, Vec<EnvelopeOut>), (&'static str, &'static str)>,
+) {
+  match res {
+    Ok((room_id, resp_payload, events)) => {
+      hub.send_json(username, &EnvelopeOut::resp_ok(req, resp_payload));
+      let participants = rooms.participants(room_id).await;
+      for evt in events {
+        for u in &participants {
+          hub.send_json(u, &evt);
+        }
+      }
+    }
+    Err((code, msg)) => hub.send_json(username, &EnvelopeOut::resp_err(req, code, msg)),
+  }
+}
+
+pub(crate) async fn leave_room_with_broadcast(
   hub: &Hub,
   rooms: &RoomService,
   room_id: Uuid,
@@ -56,7 +86,48 @@ async fn leave_room_with_broadcast(
   true
 }
 
-async fn handle_room_create(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+fn room_create_error_message(code: &str) -> &'static str {
+  match code {
+    "invalid_board_size" => "棋盘大小超出范围",
+    "invalid_room_access" => "只有私密房间可以设置密码",
+    "invalid_time_control" => "计时规则参数错误",
+    _ => "创建房间失败",
+  }
+}
+
+/// Drops `username`'s matchmaking ticket once they settle into a room of their own accord, so a
+/// later pairing doesn't pull them back out of it.
+fn withdraw_ticket(hub: &Hub, mm: &Matchmaker, username: &str) {
+  if mm.cancel(username) {
+    hub.send_json(
+      username,
+      &EnvelopeOut::event("matchmaking.cancelled", serde_json::json!({ "reason": "joinedRoom" })),
+    );
+  }
+}
+
+async fn handle_room_create(
+  hub: &Hub,
+  rooms: &RoomService,
+  mm: &Matchmaker,
+  username: &str,
+  req: &EnvelopeIn,
+) {
+  // A bare `room.create` (no payload) gets the default options.
+  let options = match &req.payload {
+    serde_json::Value::Null => Ok(RoomOptions::default()),
+    payload => serde_json::from_value::<RoomOptions>(payload.clone()),
+  };
+  let Ok(options) = options else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "房间规则参数错误"));
+    return;
+  };
+  // Validate before touching the current room, so a bad request leaves the user where they are.
+  if let Err(code) = options.validate() {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, code, room_create_error_message(code)));
+    return;
+  }
+
   // Enforce single-room: leaving previous room avoids "ghost rooms" where the creator
   // is still occupying a seat but can no longer interact with that room.
   if let Some(old_room_id) = rooms.room_id_for_user(username) {
@@ -74,13 +145,20 @@ async fn handle_room_create(hub: &Hub, rooms: &RoomService, username: &str, req:
     .and_then(|v| v.as_str())
     .unwrap_or("房间")
     .to_string();
-  let (room_id, snapshot) = rooms.create_room(username, title).await;
+  let (room_id, snapshot) = match rooms.create_room_with_options(username, title, options).await {
+    Ok(v) => v,
+    Err(code) => {
+      hub.send_json(username, &EnvelopeOut::resp_err(req, code, room_create_error_message(code)));
+      return;
+    }
+  };
   tracing::info!(
     username = %username,
     room_id = %room_id,
     rooms = ?rooms.debug_room_ids(),
     "room.create: created"
   );
+  withdraw_ticket(hub, mm, username);
 
   let resp = EnvelopeOut::resp_ok(
     req,
@@ -91,7 +169,7 @@ async fn handle_room_create(hub: &Hub, rooms: &RoomService, username: &str, req:
   hub.send_json(username, &evt);
 }
 
-async fn handle_room_join(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+async fn handle_room_join(hub: &Hub, rooms: &RoomService, mm: &Matchmaker, username: &str, req: &EnvelopeIn) {
   let Some(room_id) = req
     .payload
     .get("roomId")
@@ -101,18 +179,29 @@ async fn handle_room_join(hub: &Hub, rooms: &RoomService, username: &str, req: &
     hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "缺少 roomId"));
     return;
   };
+  // Private rooms take either their password or an invite code; a client may send both.
+  let credentials: Vec<&str> = ["password", "inviteCode"]
+    .iter()
+    .filter_map(|k| req.payload.get(*k).and_then(|v| v.as_str()))
+    .collect();
+
+  // Don't give up the current room for one we can't enter.
+  if let Err(code) = rooms.check_room_access(username, room_id, &credentials).await {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, code, join_error_message(code)));
+    return;
+  }
 
   // If user is already in another room, leave it first to keep user_room mapping sane.
-  if let Some(old_room_id) = rooms.room_id_for_user(username) {
-    if old_room_id != room_id {
-      tracing::info!(
-        username = %username,
-        old_room_id = %old_room_id,
-        new_room_id = %room_id,
-        "room.join: leaving previous room first"
-      );
-      let _ = leave_room_with_broadcast(hub, rooms, old_room_id, username).await;
-    }
+  if let Some(old_room_id) = rooms.room_id_for_user(username)
+    && old_room_id != room_id
+  {
+    tracing::info!(
+      username = %username,
+      old_room_id = %old_room_id,
+      new_room_id = %room_id,
+      "room.join: leaving previous room first"
+    );
+    let _ = leave_room_with_broadcast(hub, rooms, old_room_id, username).await;
   }
 
   tracing::info!(
@@ -122,7 +211,7 @@ async fn handle_room_join(hub: &Hub, rooms: &RoomService, username: &str, req: &
     "room.join: attempt"
   );
 
-  match rooms.join_room(username, room_id).await {
+  match rooms.join_room_with_credential(username, room_id, &credentials).await {
     Ok(snapshot) => {
       tracing::info!(
         username = %username,
@@ -130,9 +219,11 @@ async fn handle_room_join(hub: &Hub, rooms: &RoomService, username: &str, req: &
         user_room = ?rooms.debug_room_id_for_user(username),
         "room.join: ok"
       );
+      withdraw_ticket(hub, mm, username);
+      let chat = rooms.chat_history(room_id, username).await;
       hub.send_json(
         username,
-        &EnvelopeOut::resp_ok(req, serde_json::json!({ "room": snapshot })),
+        &EnvelopeOut::resp_ok(req, serde_json::json!({ "room": snapshot, "chat": chat })),
       );
       broadcast_room_snapshot(hub, rooms, room_id, serde_json::to_value(snapshot).unwrap()).await;
     }
@@ -144,9 +235,201 @@ async fn handle_room_join(hub: &Hub, rooms: &RoomService, username: &str, req: &
         code = %code,
         "room.join: err"
       );
+      hub.send_json(username, &EnvelopeOut::resp_err(req, code, join_error_message(code)));
+    }
+  }
+}
+
+fn join_error_message(code: &str) -> &'static str {
+  match code {
+    "room_not_found" => "房间不存在",
+    "room_locked" => "需要正确的房间密码或邀请码",
+    "rate_limited" => "尝试次数过多，请稍后再试",
+    "banned" => "你已被房主禁止进入该房间",
+    _ => "加入房间失败",
+  }
+}
+
+async fn handle_room_create_invite(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  match rooms.create_invite(username).await {
+    Ok((room_id, code)) => hub.send_json(
+      username,
+      &EnvelopeOut::resp_ok(
+        req,
+        serde_json::json!({ "roomId": room_id.to_string(), "inviteCode": code }),
+      ),
+    ),
+    Err(code) => hub.send_json(username, &EnvelopeOut::resp_err(req, code, "生成邀请码失败")),
+  }
+}
+
+async fn handle_room_revoke_invite(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  match rooms.revoke_invite(username).await {
+    Ok(room_id) => hub.send_json(
+      username,
+      &EnvelopeOut::resp_ok(req, serde_json::json!({ "roomId": room_id.to_string() })),
+    ),
+    Err(code) => hub.send_json(username, &EnvelopeOut::resp_err(req, code, "撤销邀请码失败")),
+  }
+}
+
+fn target_username(req: &EnvelopeIn) -> Option<&str> {
+  req.payload.get("username").and_then(|v| v.as_str()).filter(|u| !u.is_empty())
+}
+
+fn moderation_error_message(code: &str) -> &'static str {
+  match code {
+    "forbidden" => "只有房主可以执行该操作",
+    "not_in_room" => "该用户不在房间中",
+    "invalid_room_state" => "对局进行中，无法移出棋手",
+    _ => "操作失败",
+  }
+}
+
+async fn handle_room_kick(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn, ban: bool) {
+  let Some(target) = target_username(req) else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "缺少 username"));
+    return;
+  };
+  let res = if ban {
+    rooms.ban(username, target).await
+  } else {
+    rooms.kick(username, target).await.map(|(room_id, snapshot)| (room_id, snapshot, true))
+  };
+  let (room_id, snapshot, removed) = match res {
+    Ok(v) => v,
+    Err(code) => {
+      hub.send_json(username, &EnvelopeOut::resp_err(req, code, moderation_error_message(code)));
+      return;
+    }
+  };
+
+  hub.send_json(
+    username,
+    &EnvelopeOut::resp_ok(req, serde_json::json!({ "room": snapshot })),
+  );
+  if removed {
+    hub.send_json(
+      target,
+      &EnvelopeOut::event(
+        "room.kicked",
+        serde_json::json!({ "roomId": room_id.to_string(), "by": username, "banned": ban }),
+      ),
+    );
+    broadcast_room_snapshot(hub, rooms, room_id, serde_json::to_value(snapshot).unwrap()).await;
+  }
+}
+
+/// Picks the engine a `room.addBot` request asks for: a configured external engine by `engine`
+/// name, or the built-in one at `difficulty`. Returns the bot's name label alongside it.
+fn requested_engine(
+  engines: &[EngineSpec],
+  req: &EnvelopeIn,
+) -> Result<(String, Box<dyn bots::Engine>), &'static str> {
+  if let Some(name) = req.payload.get("engine").and_then(|v| v.as_str()) {
+    let spec = engines.iter().find(|e| e.name == name).ok_or("engine_not_found")?;
+    return Ok((spec.name.clone(), Box::new(PiskvorkEngine::new(spec.clone()))));
+  }
+  let difficulty = match req.payload.get("difficulty") {
+    None => Difficulty::default(),
+    Some(v) => serde_json::from_value::<Difficulty>(v.clone()).map_err(|_| "bad_request")?,
+  };
+  let label = serde_json::to_value(difficulty).unwrap().as_str().unwrap_or_default().to_string();
+  Ok((label, Box::new(bots::Builtin(difficulty))))
+}
+
+async fn handle_room_add_bot(
+  hub: &Hub,
+  rooms: &RoomService,
+  engines: &[EngineSpec],
+  username: &str,
+  req: &EnvelopeIn,
+) {
+  let (label, engine) = match requested_engine(engines, req) {
+    Ok(v) => v,
+    Err(code) => {
+      let msg = match code {
+        "engine_not_found" => "未找到该引擎",
+        _ => "difficulty 只能是 easy/medium/hard",
+      };
+      hub.send_json(username, &EnvelopeOut::resp_err(req, code, msg));
+      return;
+    }
+  };
+
+  let bot = bots::bot_username(&label);
+  match rooms.add_bot(username, &bot).await {
+    Ok((room_id, snapshot)) => {
+      bots::spawn(hub.clone(), rooms.clone(), bot.clone(), engine);
+      hub.send_json(
+        username,
+        &EnvelopeOut::resp_ok(req, serde_json::json!({ "bot": bot, "room": snapshot })),
+      );
+      broadcast_room_snapshot(hub, rooms, room_id, serde_json::to_value(snapshot).unwrap()).await;
+    }
+    Err(code) => {
+      let msg = match code {
+        "forbidden" => "只有房主可以添加电脑",
+        "bot_not_allowed" => "排位房间不能添加电脑",
+        "seat_taken" => "没有空座位",
+        "invalid_room_state" => "对局进行中",
+        _ => "添加电脑失败",
+      };
+      hub.send_json(username, &EnvelopeOut::resp_err(req, code, msg));
+    }
+  }
+}
+
+async fn handle_room_transfer_owner(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let Some(target) = target_username(req) else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "缺少 username"));
+    return;
+  };
+  match rooms.transfer_owner(username, target).await {
+    Ok((room_id, snapshot)) => {
+      hub.send_json(
+        username,
+        &EnvelopeOut::resp_ok(req, serde_json::json!({ "room": snapshot })),
+      );
+      broadcast_room_snapshot(hub, rooms, room_id, serde_json::to_value(snapshot).unwrap()).await;
+    }
+    Err(code) => hub.send_json(username, &EnvelopeOut::resp_err(req, code, moderation_error_message(code))),
+  }
+}
+
+async fn handle_room_chat(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let Some(text) = req.payload.get("text").and_then(|v| v.as_str()) else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "缺少 text"));
+    return;
+  };
+  let channel = req
+    .payload
+    .get("channel")
+    .and_then(|v| serde_json::from_value::<ChatChannel>(v.clone()).ok())
+    .unwrap_or_default();
+
+  match rooms.send_chat(username, text, channel).await {
+    Ok((room_id, message, recipients)) => {
+      hub.send_json(
+        username,
+        &EnvelopeOut::resp_ok(req, serde_json::json!({ "message": message })),
+      );
+      let evt = EnvelopeOut::event("room.chat", serde_json::to_value(&message).unwrap());
+      match recipients {
+        Some(users) => {
+          for u in users {
+            hub.send_json(&u, &evt);
+          }
+        }
+        None => broadcast_room_event(hub, rooms, room_id, &evt).await,
+      }
+    }
+    Err(code) => {
       let msg = match code {
-        "room_not_found" => "房间不存在",
-        _ => "加入房间失败",
+        "message_too_long" => "消息过长",
+        "rate_limited" => "发言过于频繁，请稍后再试",
+        "forbidden" => "棋手不能在观众频道发言",
+        _ => "发送失败",
       };
       hub.send_json(username, &EnvelopeOut::resp_err(req, code, msg));
     }
@@ -166,7 +449,13 @@ async fn handle_room_leave(hub: &Hub, rooms: &RoomService, username: &str, req:
   hub.send_json(username, &EnvelopeOut::resp_ok(req, serde_json::json!({})));
 }
 
-async fn handle_room_take_seat(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+async fn handle_room_take_seat(
+  hub: &Hub,
+  rooms: &RoomService,
+  mm: &Matchmaker,
+  username: &str,
+  req: &EnvelopeIn,
+) {
   let seat_str = req
     .payload
     .get("seat")
@@ -187,6 +476,7 @@ async fn handle_room_take_seat(hub: &Hub, rooms: &RoomService, username: &str, r
 
   match rooms.take_seat(username, seat).await {
     Ok((room_id, snapshot)) => {
+      withdraw_ticket(hub, mm, username);
       hub.send_json(
         username,
         &EnvelopeOut::resp_ok(req, serde_json::json!({ "room": snapshot })),
@@ -199,10 +489,11 @@ async fn handle_room_take_seat(hub: &Hub, rooms: &RoomService, username: &str, r
   }
 }
 
-async fn handle_room_ready(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+async fn handle_room_ready(hub: &Hub, rooms: &RoomService, mm: &Matchmaker, username: &str, req: &EnvelopeIn) {
   let ready = req.payload.get("ready").and_then(|v| v.as_bool()).unwrap_or(false);
   match rooms.set_ready(username, ready).await {
     Ok((room_id, snapshot, match_start_evt)) => {
+      withdraw_ticket(hub, mm, username);
       hub.send_json(
         username,
         &EnvelopeOut::resp_ok(req, serde_json::json!({ "room": snapshot })),
@@ -232,35 +523,333 @@ async fn handle_match_move(hub: &Hub, rooms: &RoomService, username: &str, req:
     return;
   };
 
-  match rooms.match_move(username, coord).await {
-    Ok((room_id, resp_payload, events)) => {
-      hub.send_json(username, &EnvelopeOut::resp_ok(req, resp_payload));
-      let participants = rooms.participants(room_id).await;
-      for evt in events {
-        for u in &participants {
-          hub.send_json(u, &evt);
+  let res = rooms.match_move(username, coord).await;
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_opening_choice(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let choice = req
+    .payload
+    .get("choice")
+    .and_then(|v| serde_json::from_value::<OpeningChoice>(v.clone()).ok());
+  let Some(choice) = choice else {
+    hub.send_json(
+      username,
+      &EnvelopeOut::resp_err(req, "bad_request", "choice 只能是 black/white/place2"),
+    );
+    return;
+  };
+
+  let res = rooms.match_opening_choice(username, choice).await;
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_hint(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let (room_id, m, events) = match rooms.match_hint(username).await {
+    Ok(v) => v,
+    Err((code, msg)) => {
+      hub.send_json(username, &EnvelopeOut::resp_err(req, code, msg));
+      return;
+    }
+  };
+
+  let position = Position::from_match(&m);
+  let board = position.board();
+  let (to_move, rule) = (position.to_move, position.rule);
+  let res = tokio::task::spawn_blocking(move || analysis::analyze(&board, to_move, rule, analysis::HINT_TOP)).await;
+  let Ok(mut hint) = res else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "internal_error", "服务器内部错误"));
+    return;
+  };
+  // The engine doesn't know about Pro-style opening restrictions.
+  hint
+    .candidates
+    .retain(|c| m.opening.permits(m.board_size, m.moves.len(), &c.coord));
+
+  hub.send_json(
+    username,
+    &EnvelopeOut::resp_ok(
+      req,
+      serde_json::json!({
+        "candidates": hint.candidates,
+        "threats": hint.threats,
+        "hintsLeft": m.hints_left,
+      }),
+    ),
+  );
+  for evt in &events {
+    broadcast_room_event(hub, rooms, room_id, evt).await;
+  }
+}
+
+async fn handle_match_resign(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let res = rooms.match_resign(username).await;
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_rematch(
+  hub: &Hub,
+  rooms: &RoomService,
+  mm: &Matchmaker,
+  username: &str,
+  req: &EnvelopeIn,
+) {
+  let res = rooms.match_rematch(username).await;
+  if res.is_ok() {
+    withdraw_ticket(hub, mm, username);
+  }
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_offer_draw(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let res = rooms.match_offer_draw(username).await;
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_respond_draw(
+  hub: &Hub,
+  rooms: &RoomService,
+  username: &str,
+  req: &EnvelopeIn,
+  accept: bool,
+) {
+  let res = rooms.match_respond_draw(username, accept).await;
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_request_undo(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let res = rooms.match_request_undo(username).await;
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_respond_undo(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let Some(accept) = req.payload.get("accept").and_then(|v| v.as_bool()) else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "缺少 accept"));
+    return;
+  };
+  let res = rooms.match_respond_undo(username, accept).await;
+  reply_and_broadcast(hub, rooms, username, req, res).await;
+}
+
+async fn handle_match_state(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let Some(room_id) = rooms.room_id_for_user(username) else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "not_in_room", "未加入房间"));
+    return;
+  };
+  match rooms.match_state(room_id).await {
+    Some(state) => hub.send_json(
+      username,
+      &EnvelopeOut::resp_ok(req, serde_json::json!({ "match": state })),
+    ),
+    None => hub.send_json(username, &EnvelopeOut::resp_err(req, "match_not_found", "对局不存在")),
+  }
+}
+
+async fn handle_matchmaking_enqueue(
+  hub: &Hub,
+  rooms: &RoomService,
+  mm: &Matchmaker,
+  username: &str,
+  req: &EnvelopeIn,
+) {
+  let Ok(options) = serde_json::from_value::<RoomOptions>(req.payload.clone()) else {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "房间规则参数错误"));
+    return;
+  };
+  if let Err(code) = options.validate() {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, code, "房间规则参数错误"));
+    return;
+  }
+  if rooms.is_playing(username).await {
+    hub.send_json(username, &EnvelopeOut::resp_err(req, "already_playing", "对局进行中，无法匹配"));
+    return;
+  }
+
+  let rating = match rooms.rating(username) {
+    Some(r) => r.rating,
+    None if options.rated => {
+      hub.send_json(username, &EnvelopeOut::resp_err(req, "rating_unavailable", "积分未加载，无法进行积分匹配"));
+      return;
+    }
+    None => ratings::Rating::default().rating,
+  };
+  mm.enqueue(username, rating, options, tokio::time::Instant::now());
+  hub.send_json(username, &EnvelopeOut::resp_ok(req, serde_json::json!({ "queued": true })));
+  run_matchmaking(hub, rooms, mm).await;
+}
+
+async fn handle_matchmaking_cancel(hub: &Hub, mm: &Matchmaker, username: &str, req: &EnvelopeIn) {
+  let cancelled = mm.cancel(username);
+  hub.send_json(
+    username,
+    &EnvelopeOut::resp_ok(req, serde_json::json!({ "cancelled": cancelled })),
+  );
+}
+
+/// Seats every pair the matchmaker can currently form in a fresh room, with random colors.
+async fn run_matchmaking(hub: &Hub, rooms: &RoomService, mm: &Matchmaker) {
+  for pairing in mm.pair(tokio::time::Instant::now()) {
+    // A player may have sat down to a match since queueing; never pull them out of it. Their
+    // opponent goes back in the queue with the time already waited.
+    let busy = [
+      rooms.is_playing(&pairing.first).await,
+      rooms.is_playing(&pairing.second).await,
+    ];
+    if busy.contains(&true) {
+      for (u, busy) in [(&pairing.first, busy[0]), (&pairing.second, busy[1])] {
+        if !busy {
+          mm.requeue(&pairing, u);
         }
       }
+      continue;
+    }
+    let (black, white) = if rand::random::<bool>() {
+      (pairing.first.clone(), pairing.second.clone())
+    } else {
+      (pairing.second.clone(), pairing.first.clone())
+    };
+    for u in [&black, &white] {
+      if let Some(old_room_id) = rooms.room_id_for_user(u) {
+        let _ = leave_room_with_broadcast(hub, rooms, old_room_id, u).await;
+      }
+    }
+
+    let title = format!("{black} vs {white}");
+    let (room_id, snapshot, start_evt) =
+      match rooms.create_paired_room(&black, &white, title, pairing.options).await {
+        Ok(v) => v,
+        Err(code) => {
+          tracing::warn!(black = %black, white = %white, code = %code, "matchmaking: failed to seat pair");
+          let evt = EnvelopeOut::event("matchmaking.failed", serde_json::json!({ "code": code }));
+          for u in [&black, &white] {
+            hub.send_json(u, &evt);
+          }
+          continue;
+        }
+      };
+    tracing::info!(black = %black, white = %white, room_id = %room_id, "matchmaking: paired");
+
+    for (me, color, opponent) in [(&black, "black", &white), (&white, "white", &black)] {
+      let evt = EnvelopeOut::event(
+        "matchmaking.found",
+        serde_json::json!({
+          "roomId": room_id.to_string(),
+          "color": color,
+          "opponent": opponent,
+          "room": snapshot
+        }),
+      );
+      hub.send_json(me, &evt);
+    }
+    if let Some(evt) = start_evt {
+      broadcast_room_event(hub, rooms, room_id, &evt).await;
     }
-    Err((code, msg)) => hub.send_json(username, &EnvelopeOut::resp_err(req, code, msg)),
   }
 }
 
-async fn dispatch_ws_req(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+async fn handle_lobby_list(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  let list = rooms.lobby_list().await;
+  hub.send_json(username, &EnvelopeOut::resp_ok(req, serde_json::json!({ "rooms": list })));
+}
+
+async fn handle_lobby_subscribe(hub: &Hub, rooms: &RoomService, username: &str, req: &EnvelopeIn) {
+  hub.lobby_subscribers.insert(username.to_string());
+  // Reply with the full list so the client has a base to apply deltas to.
+  handle_lobby_list(hub, rooms, username, req).await;
+}
+
+async fn handle_lobby_unsubscribe(hub: &Hub, username: &str, req: &EnvelopeIn) {
+  hub.lobby_subscribers.remove(username);
+  hub.send_json(username, &EnvelopeOut::resp_ok(req, serde_json::json!({})));
+}
+
+/// Forwards room list changes to every `lobby.subscribe`d connection.
+pub fn spawn_lobby_forwarder(hub: Hub, rooms: RoomService) -> tokio::task::JoinHandle<()> {
+  let mut rx = rooms.subscribe_lobby();
+  tokio::spawn(async move {
+    loop {
+      match rx.recv().await {
+        Ok(evt) => {
+          let evt = evt.to_envelope();
+          for u in hub.lobby_subscribers.iter() {
+            hub.send_json(u.key(), &evt);
+          }
+        }
+        Err(broadcast::error::RecvError::Lagged(n)) => {
+          tracing::warn!(skipped = n, "lobby: forwarder lagged, deltas dropped");
+        }
+        Err(broadcast::error::RecvError::Closed) => break,
+      }
+    }
+  })
+}
+
+async fn dispatch_ws_req(
+  hub: &Hub,
+  rooms: &RoomService,
+  mm: &Matchmaker,
+  engines: &[EngineSpec],
+  username: &str,
+  req: &EnvelopeIn,
+) {
   match req.r#type.as_str() {
-    "room.create" => handle_room_create(hub, rooms, username, req).await,
-    "room.join" => handle_room_join(hub, rooms, username, req).await,
+    "room.create" => handle_room_create(hub, rooms, mm, username, req).await,
+    "room.join" => handle_room_join(hub, rooms, mm, username, req).await,
     "room.leave" => handle_room_leave(hub, rooms, username, req).await,
-    "room.takeSeat" => handle_room_take_seat(hub, rooms, username, req).await,
-    "room.ready" => handle_room_ready(hub, rooms, username, req).await,
+    "room.takeSeat" => handle_room_take_seat(hub, rooms, mm, username, req).await,
+    "room.ready" => handle_room_ready(hub, rooms, mm, username, req).await,
+    "room.createInvite" => handle_room_create_invite(hub, rooms, username, req).await,
+    "room.revokeInvite" => handle_room_revoke_invite(hub, rooms, username, req).await,
+    "room.kick" => handle_room_kick(hub, rooms, username, req, false).await,
+    "room.ban" => handle_room_kick(hub, rooms, username, req, true).await,
+    "room.transferOwner" => handle_room_transfer_owner(hub, rooms, username, req).await,
+    "room.addBot" => handle_room_add_bot(hub, rooms, engines, username, req).await,
+    "room.chat" => handle_room_chat(hub, rooms, username, req).await,
     "match.move" => handle_match_move(hub, rooms, username, req).await,
+    "match.state" => handle_match_state(hub, rooms, username, req).await,
+    "match.openingChoice" => handle_match_opening_choice(hub, rooms, username, req).await,
+    "match.resign" => handle_match_resign(hub, rooms, username, req).await,
+    "match.hint" => handle_match_hint(hub, rooms, username, req).await,
+    "match.rematch" => handle_match_rematch(hub, rooms, mm, username, req).await,
+    "match.offerDraw" => handle_match_offer_draw(hub, rooms, username, req).await,
+    "match.acceptDraw" => handle_match_respond_draw(hub, rooms, username, req, true).await,
+    "match.declineDraw" => handle_match_respond_draw(hub, rooms, username, req, false).await,
+    "match.requestUndo" => handle_match_request_undo(hub, rooms, username, req).await,
+    "match.respondUndo" => handle_match_respond_undo(hub, rooms, username, req).await,
+    "matchmaking.enqueue" => handle_matchmaking_enqueue(hub, rooms, mm, username, req).await,
+    "matchmaking.cancel" => handle_matchmaking_cancel(hub, mm, username, req).await,
+    "lobby.list" => handle_lobby_list(hub, rooms, username, req).await,
+    "lobby.subscribe" => handle_lobby_subscribe(hub, rooms, username, req).await,
+    "lobby.unsubscribe" => handle_lobby_unsubscribe(hub, username, req).await,
     _ => hub.send_json(username, &EnvelopeOut::resp_err(req, "bad_request", "未知消息类型")),
   }
 }
 
+/// Background loop for time-based events: clock flag falls, expired reconnect windows,
+/// matchmaking windows widening enough to pair waiting players, and stale rate-limit entries.
+pub fn spawn_ticker(hub: Hub, rooms: RoomService, mm: Matchmaker) -> tokio::task::JoinHandle<()> {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(250));
+    loop {
+      interval.tick().await;
+      let mut due = rooms.expire_clocks().await;
+      due.extend(rooms.expire_held_seats().await);
+      for (room_id, events) in due {
+        for evt in events {
+          broadcast_room_event(&hub, &rooms, room_id, &evt).await;
+        }
+      }
+      run_matchmaking(&hub, &rooms, &mm).await;
+      rooms.prune_rate_limits();
+    }
+  })
+}
+
 #[derive(Default, Clone)]
 pub struct Hub {
   conns: std::sync::Arc<DashMap<String, mpsc::UnboundedSender<Message>>>,
+  // Users whose current connection sent `lobby.subscribe`.
+  lobby_subscribers: std::sync::Arc<DashSet<String>>,
 }
 
 impl Hub {
@@ -297,7 +886,9 @@ impl Hub {
         }
     }
 
-    fn register(&self, username: String, tx: mpsc::UnboundedSender<Message>) {
+    pub(crate) fn register(&self, username: String, tx: mpsc::UnboundedSender<Message>) {
+        // A new connection starts without lobby updates until it asks for them.
+        self.lobby_subscribers.remove(&username);
         // Replace existing connection if any (single-session).
         if let Some(old) = self.conns.insert(username.clone(), tx) {
             let _ = old.send(Message::Text(
@@ -316,8 +907,18 @@ impl Hub {
         }
     }
 
-    fn unregister(&self, username: &str) {
-        self.conns.remove(username);
+    /// Whether `username` is connected through some channel other than `tx`, i.e. a newer
+    /// connection has already replaced this one.
+    fn has_other_connection(&self, username: &str, tx: &mpsc::UnboundedSender<Message>) -> bool {
+        self.conns
+            .get(username)
+            .is_some_and(|cur| !cur.value().same_channel(tx))
+    }
+
+    pub(crate) fn unregister(&self, username: &str, tx: &mpsc::UnboundedSender<Message>) {
+        if self.conns.remove_if(username, |_, cur| cur.same_channel(tx)).is_some() {
+            self.lobby_subscribers.remove(username);
+        }
     }
 }
 
@@ -336,9 +937,7 @@ struct Envelope {
 }
 
 pub async fn ws_handler(
-    State(cfg): State<Config>,
-    State(hub): State<Hub>,
-    State(rooms): State<RoomService>,
+    State(app): State<AppState>,
     Query(q): Query<WsQuery>,
     ws: WebSocketUpgrade,
     headers: axum::http::HeaderMap,
@@ -355,16 +954,38 @@ pub async fn ws_handler(
         })
         .unwrap_or_default();
 
+    let AppState {
+        cfg,
+        pool,
+        hub,
+        rooms,
+        matchmaker: mm,
+    } = app;
     let Ok(claims) = auth::verify_access_token(&cfg, &token) else {
         // Cannot return JSON here; just refuse upgrade by returning 401.
         return (axum::http::StatusCode::UNAUTHORIZED, "unauthorized").into_response();
     };
 
     let username = claims.sub;
-    ws.on_upgrade(move |socket| handle_socket(socket, hub, rooms, username))
+    match ratings::load(&pool, &username).await {
+        Ok(Some(rating)) => rooms.seed_rating(&username, rating),
+        Ok(None) => {}
+        Err(_) => tracing::warn!(username = %username, "ws: failed to load rating"),
+    }
+    let grace = std::time::Duration::from_secs(cfg.reconnect_grace_secs);
+    let engines = cfg.engines.clone();
+    ws.on_upgrade(move |socket| handle_socket(socket, hub, rooms, mm, engines, username, grace))
 }
 
-async fn handle_socket(socket: WebSocket, hub: Hub, rooms: RoomService, username: String) {
+async fn handle_socket(
+    socket: WebSocket,
+    hub: Hub,
+    rooms: RoomService,
+    mm: Matchmaker,
+    engines: std::sync::Arc<[EngineSpec]>,
+    username: String,
+    grace: std::time::Duration,
+) {
     let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
     let out_tx = tx.clone();
     hub.register(username.clone(), tx);
@@ -380,14 +1001,25 @@ async fn handle_socket(socket: WebSocket, hub: Hub, rooms: RoomService, username
         }
     });
 
-    // On connect, if already in a room, push current snapshot.
-    if let Some(room_id) = rooms.room_id_for_user(&username) {
-        if let Some(snapshot) = rooms.snapshot(room_id).await {
-            let evt = EnvelopeOut::event("room.snapshot", serde_json::to_value(snapshot).unwrap());
-            let _ = out_tx.send(Message::Text(serde_json::to_string(&evt).unwrap().into()));
+    // Reclaim a seat held since an earlier disconnect and tell the opponent we're back.
+    if let Some((room_id, events)) = rooms.resume_seat(&username).await {
+        tracing::info!(username = %username, room_id = %room_id, "ws: reconnected to held seat");
+        for evt in events {
+            broadcast_room_event(&hub, &rooms, room_id, &evt).await;
         }
     }
 
+    // On connect, if already in a room, push current snapshot (including any running match).
+    if let Some(room_id) = rooms.room_id_for_user(&username)
+        && let Some(snapshot) = rooms.snapshot(room_id).await
+    {
+        let evt = EnvelopeOut::event("room.snapshot", serde_json::to_value(snapshot).unwrap());
+        let _ = out_tx.send(Message::Text(serde_json::to_string(&evt).unwrap().into()));
+        let chat = rooms.chat_history(room_id, &username).await;
+        let evt = EnvelopeOut::event("room.chatHistory", serde_json::json!({ "messages": chat }));
+        let _ = out_tx.send(Message::Text(serde_json::to_string(&evt).unwrap().into()));
+    }
+
     // Message loop.
     while let Some(Ok(msg)) = receiver.next().await {
         match msg {
@@ -413,7 +1045,7 @@ async fn handle_socket(socket: WebSocket, hub: Hub, rooms: RoomService, username
                 }
 
                 // Dispatch.
-                dispatch_ws_req(&hub, &rooms, &username, &req).await;
+                dispatch_ws_req(&hub, &rooms, &mm, &engines, &username, &req).await;
             }
             Message::Ping(v) => {
                 let _ = out_tx.send(Message::Pong(v));
@@ -423,17 +1055,36 @@ async fn handle_socket(socket: WebSocket, hub: Hub, rooms: RoomService, username
         }
     }
 
-    // Treat WS disconnect as leaving current room.
+    // A newer connection for the same user already took over; it owns the room state now.
+    if hub.has_other_connection(&username_for_tx, &out_tx) {
+        tracing::info!(username = %username_for_tx, "ws: superseded by newer connection");
+        send_task.abort();
+        return;
+    }
+
+    mm.cancel(&username_for_tx);
+
+    // Seated players in a running match get a grace window; everyone else leaves the room.
     tracing::info!(
       username = %username_for_tx,
       user_room = ?rooms.debug_room_id_for_user(&username_for_tx),
       "ws: disconnected, leaving room"
     );
-    let left = rooms.leave_room(&username_for_tx).await;
-    if let Some((snapshot, _)) = &left {
+    if let Some((room_id, events)) = rooms.hold_seat(&username_for_tx, grace).await {
+        tracing::info!(
+          username = %username_for_tx,
+          room_id = %room_id,
+          grace_secs = grace.as_secs(),
+          "ws: holding seat"
+        );
+        for evt in events {
+            broadcast_room_event(&hub, &rooms, room_id, &evt).await;
+        }
+    } else if let Some(room_id) = rooms.room_id_for_user(&username_for_tx) {
+        leave_room_with_broadcast(&hub, &rooms, room_id, &username_for_tx).await;
         tracing::info!(
           username = %username_for_tx,
-          room_id = %snapshot.room_id,
+          room_id = %room_id,
           rooms = ?rooms.debug_room_ids(),
           "ws: left room"
         );
@@ -444,6 +1095,8 @@ async fn handle_socket(socket: WebSocket, hub: Hub, rooms: RoomService, username
           "ws: not in room"
         );
     }
-    hub.unregister(&username_for_tx);
+    // Held seats keep the rating for the match they are still part of.
+    rooms.forget_rating(&username_for_tx);
+    hub.unregister(&username_for_tx, &out_tx);
     send_task.abort();
 }
@@ -0,0 +1,110 @@
+use server::ai::{self, Difficulty};
+use server::board::{Board, BLACK, WHITE};
+use server::bots::{self, Builtin};
+use server::opening::OpeningRule;
+use server::rooms::{Color, Coord, RoomOptions, RoomService, RoomState, SeatKind};
+use server::rules::{self, RuleSet};
+use server::ws::Hub;
+
+fn board_with(stones: &[(usize, usize, u8)]) -> Board {
+  let mut b: Board = Default::default();
+  for &(r, c, v) in stones {
+    b[r][c] = v;
+  }
+  b
+}
+
+fn at(coord: Option<Coord>) -> Option<(i32, i32)> {
+  coord.map(|c| (c.row, c.col))
+}
+
+#[test]
+fn engine_finds_wins_and_blocks() {
+  for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+    // White completes its own four rather than blocking Black's.
+    let b = board_with(&[
+      (7, 3, BLACK),
+      (7, 4, BLACK),
+      (7, 5, BLACK),
+      (7, 6, BLACK),
+      (2, 3, WHITE),
+      (3, 3, WHITE),
+      (4, 3, WHITE),
+      (5, 3, WHITE),
+    ]);
+    let mv = at(ai::choose_move(&b, Color::White, RuleSet::Freestyle, difficulty));
+    assert!(mv == Some((1, 3)) || mv == Some((6, 3)), "{difficulty:?} played {mv:?}");
+
+    // Nothing to win with, so White has to stop the four.
+    let b = board_with(&[(7, 3, BLACK), (7, 4, BLACK), (7, 5, BLACK), (7, 6, BLACK), (7, 2, WHITE)]);
+    let mv = at(ai::choose_move(&b, Color::White, RuleSet::Freestyle, difficulty));
+    assert_eq!(mv, Some((7, 7)), "{difficulty:?}");
+  }
+}
+
+#[test]
+fn engine_avoids_renju_forbidden_points() {
+  // (7,7) would be a double three for Black and is the natural-looking point.
+  let b = board_with(&[(7, 5, BLACK), (7, 6, BLACK), (5, 7, BLACK), (6, 7, BLACK), (0, 0, WHITE), (0, 2, WHITE)]);
+  assert!(rules::renju_forbidden(&b, 7, 7).is_some());
+  let mv = ai::choose_move(&b, Color::Black, RuleSet::Renju, Difficulty::Medium).unwrap();
+  assert_ne!((mv.row, mv.col), (7, 7));
+  assert!(rules::renju_forbidden(&b, mv.row as usize, mv.col as usize).is_none());
+}
+
+#[tokio::test]
+async fn bot_takes_the_empty_seat() {
+  let svc = RoomService::default();
+  let (room_id, _snap) = svc.create_room("alice", "t".to_string()).await;
+  assert_eq!(svc.add_bot("bob", "bot:easy-1").await.err(), Some("not_in_room"));
+  let (_, snap) = svc.add_bot("alice", "bot:easy-1").await.unwrap();
+  assert_eq!(snap.seats.white.as_ref().map(|s| s.username.as_str()), Some("bot:easy-1"));
+  assert_eq!(svc.add_bot("alice", "bot:easy-2").await.err(), Some("seat_taken"));
+
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let (_, snap, start) = svc.set_ready("bot:easy-1", true).await.unwrap();
+  assert!(start.is_some());
+  assert!(matches!(snap.state, RoomState::Playing));
+
+  // Once the last human leaves, ownership can only fall to the bot.
+  let _ = svc.leave_room("alice").await;
+  assert_eq!(svc.snapshot(room_id).await.unwrap().owner, "bot:easy-1");
+
+  let rated = RoomOptions { rated: true, ..Default::default() };
+  let _ = svc.create_room_with_options("carol", "r".to_string(), rated).await.unwrap();
+  assert_eq!(svc.add_bot("carol", "bot:easy-3").await.err(), Some("bot_not_allowed"));
+}
+
+#[tokio::test]
+async fn bot_keeps_to_pro_opening() {
+  let (svc, hub) = (RoomService::default(), Hub::default());
+  let options = RoomOptions { opening: OpeningRule::Pro, ..Default::default() };
+  let (room_id, _snap) = svc.create_room_with_options("alice", "t".to_string(), options).await.unwrap();
+  let _ = svc.take_seat("alice", SeatKind::White).await.unwrap();
+  let _ = svc.add_bot("alice", "bot:hard-1").await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  bots::spawn(hub.clone(), svc.clone(), "bot:hard-1".to_string(), Box::new(Builtin(Difficulty::Hard)));
+
+  let moves = |n: usize| {
+    let svc = svc.clone();
+    async move {
+      for _ in 0..500 {
+        if let Some(m) = svc.match_state(room_id).await
+          && m.moves.len() >= n
+        {
+          return m.moves;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+      }
+      panic!("bot did not move");
+    }
+  };
+  let first = moves(1).await;
+  assert_eq!((first[0].coord.row, first[0].coord.col), (7, 7));
+
+  let _ = svc.match_move("alice", Coord { row: 7, col: 8 }).await.unwrap();
+  // Wake the bot the way a room broadcast would.
+  hub.send("bot:hard-1", axum::extract::ws::Message::Text("{}".into()));
+  let third = &moves(3).await[2];
+  assert!((third.coord.row - 7).abs().max((third.coord.col - 7).abs()) >= 3);
+}
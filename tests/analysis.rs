@@ -0,0 +1,99 @@
+use server::analysis::{self, ThreatKind};
+use server::board::{Board, BLACK, WHITE};
+use server::ratings::Rating;
+use server::rooms::{Color, Coord, RoomOptions, RoomService, SeatKind};
+use server::rules::RuleSet;
+
+fn c(row: i32, col: i32) -> Coord {
+  Coord { row, col }
+}
+
+#[test]
+fn reports_threats_and_winning_candidates() {
+  // Black: open three on row 7; White: four on column 0 (capped at the top by the edge).
+  let moves = [c(7, 5), c(0, 0), c(7, 6), c(1, 0), c(7, 7), c(2, 0), c(12, 12), c(3, 0)];
+  let (board, to_move) = analysis::replay(15, &moves).unwrap();
+  assert_eq!(to_move, Color::Black);
+
+  let threats = analysis::threats(&board, RuleSet::Freestyle);
+  let has = |color, kind, row, col| {
+    threats
+      .iter()
+      .any(|t| t.color == color && t.kind == kind && (t.coord.row, t.coord.col) == (row, col))
+  };
+  assert!(has(Color::White, ThreatKind::Four, 4, 0));
+  assert!(has(Color::Black, ThreatKind::OpenThree, 7, 4));
+  assert!(has(Color::Black, ThreatKind::OpenThree, 7, 8));
+  assert!(!threats.iter().any(|t| t.color == Color::White && t.kind == ThreatKind::OpenThree));
+
+  // Black must block the four before anything else.
+  let result = analysis::analyze(&board, to_move, RuleSet::Freestyle, 3);
+  let best = &result.candidates[0];
+  assert_eq!((best.coord.row, best.coord.col), (4, 0));
+
+  // With White to move, the four simply wins.
+  let result = analysis::analyze(&board, Color::White, RuleSet::Freestyle, 3);
+  assert!(result.candidates[0].winning);
+}
+
+#[test]
+fn reports_forced_wins() {
+  // Black's (7, 10) makes a four and an open three at once.
+  let mut board = Board::new(15);
+  for (r, c) in [(7, 7), (7, 8), (7, 9), (8, 10), (9, 10)] {
+    board[r][c] = BLACK;
+  }
+  for (r, c) in [(7, 6), (0, 0), (0, 14), (14, 0), (14, 14)] {
+    board[r][c] = WHITE;
+  }
+  let result = analysis::analyze(&board, Color::Black, RuleSet::Freestyle, 3);
+  let vcf = result
+    .threats
+    .iter()
+    .find(|t| t.kind == ThreatKind::Vcf)
+    .expect("black has a VCF");
+  assert_eq!(vcf.color, Color::Black);
+  assert_eq!((vcf.coord.row, vcf.coord.col), (7, 10));
+  assert!(vcf.line.as_ref().is_some_and(|l| l.len() == 5));
+
+  let best = &result.candidates[0];
+  assert_eq!((best.coord.row, best.coord.col), (7, 10));
+  assert!(best.winning);
+}
+
+#[test]
+fn rejects_impossible_positions() {
+  assert!(analysis::replay(15, &[c(7, 7), c(7, 7)]).is_err());
+  assert!(analysis::replay(15, &[c(15, 0)]).is_err());
+  assert!(analysis::replay(5, &[]).is_err());
+  assert!(analysis::from_cells(&vec![vec![0; 9]; 8]).is_err());
+  assert!(analysis::from_cells(&vec![vec![3; 9]; 9]).is_err());
+}
+
+#[tokio::test]
+async fn hints_are_limited_and_unrated_only() {
+  let svc = RoomService::default();
+  let options = RoomOptions { hints_per_game: 1, ..Default::default() };
+  let (room_id, _snap) = svc.create_room_with_options("alice", "t".to_string(), options).await.unwrap();
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+
+  assert_eq!(svc.match_hint("bob").await.err().map(|e| e.0), Some("not_your_turn"));
+  let (_, m, events) = svc.match_hint("alice").await.unwrap();
+  assert_eq!(m.hints_left.map(|h| (h.black, h.white)), Some((0, 1)));
+  assert!(events.iter().any(|e| e.r#type == "match.hintUsed"));
+  assert_eq!(svc.match_hint("alice").await.err().map(|e| e.0), Some("hint_limit_reached"));
+
+  svc.set_rating("carol", Rating::default());
+  svc.set_rating("dave", Rating::default());
+  let rated = RoomOptions { rated: true, ..Default::default() };
+  let (room_id, _snap) = svc.create_room_with_options("carol", "r".to_string(), rated).await.unwrap();
+  let _ = svc.join_room("dave", room_id).await.unwrap();
+  let _ = svc.take_seat("dave", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("carol", true).await.unwrap();
+  let _ = svc.set_ready("dave", true).await.unwrap();
+  assert_eq!(svc.match_hint("carol").await.err().map(|e| e.0), Some("hints_disabled"));
+  assert!(svc.match_state(room_id).await.unwrap().hints_left.is_none());
+}
@@ -0,0 +1,73 @@
+use server::chat::{ChatChannel, RateLimiter, HISTORY_LEN, RATE_LIMIT_MESSAGES, RATE_LIMIT_WINDOW};
+use server::rooms::{RoomService, SeatKind};
+
+async fn room_with_spectator(svc: &RoomService) -> uuid::Uuid {
+  let (room_id, _snap) = svc.create_room("alice", "t".to_string()).await;
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.join_room("carol", room_id).await.unwrap();
+  room_id
+}
+
+#[tokio::test]
+async fn spectators_are_kept_off_the_players_channel_mid_game() {
+  let svc = RoomService::default();
+  let room_id = room_with_spectator(&svc).await;
+
+  let (_, msg, recipients) = svc.send_chat("carol", "good luck!", ChatChannel::All).await.unwrap();
+  assert_eq!(msg.channel, ChatChannel::All);
+  assert!(recipients.is_none());
+
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+  let (_, msg, recipients) = svc.send_chat("carol", "play 7,7", ChatChannel::All).await.unwrap();
+  assert_eq!(msg.channel, ChatChannel::Spectators);
+  assert_eq!(recipients, Some(vec!["carol".to_string()]));
+  assert_eq!(
+    svc.send_chat("alice", "hi", ChatChannel::Spectators).await.err(),
+    Some("forbidden")
+  );
+
+  let seen_by_player: Vec<_> = svc.chat_history(room_id, "alice").await.into_iter().map(|m| m.text).collect();
+  assert_eq!(seen_by_player, vec!["good luck!"]);
+  assert_eq!(svc.chat_history(room_id, "carol").await.len(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn chat_is_limited_in_length_rate_and_history() {
+  let svc = RoomService::default();
+  let room_id = room_with_spectator(&svc).await;
+
+  let long = "x".repeat(201);
+  assert_eq!(svc.send_chat("alice", &long, ChatChannel::All).await.err(), Some("message_too_long"));
+  assert_eq!(svc.send_chat("alice", "   ", ChatChannel::All).await.err(), Some("bad_request"));
+
+  for i in 0..RATE_LIMIT_MESSAGES {
+    svc.send_chat("alice", &format!("m{i}"), ChatChannel::All).await.unwrap();
+  }
+  assert_eq!(svc.send_chat("alice", "again", ChatChannel::All).await.err(), Some("rate_limited"));
+  tokio::time::advance(std::time::Duration::from_secs(10)).await;
+  svc.send_chat("alice", "again", ChatChannel::All).await.unwrap();
+
+  for i in 0..HISTORY_LEN {
+    svc.send_chat("bob", &format!("b{i}"), ChatChannel::All).await.unwrap();
+    tokio::time::advance(std::time::Duration::from_secs(3)).await;
+  }
+  let history = svc.chat_history(room_id, "carol").await;
+  assert_eq!(history.len(), HISTORY_LEN);
+  assert_eq!(history.last().unwrap().text, format!("b{}", HISTORY_LEN - 1));
+}
+
+#[test]
+fn rate_limiter_forgets_idle_users() {
+  let limiter = RateLimiter::default();
+  let t0 = tokio::time::Instant::now();
+  limiter.check("alice", t0).unwrap();
+  limiter.check("bob", t0 + RATE_LIMIT_WINDOW / 2).unwrap();
+  assert_eq!(limiter.tracked(), 2);
+
+  limiter.prune(t0 + RATE_LIMIT_WINDOW);
+  assert_eq!(limiter.tracked(), 1);
+  limiter.prune(t0 + RATE_LIMIT_WINDOW * 2);
+  assert_eq!(limiter.tracked(), 0);
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use server::clock::{MatchClock, TimeControl};
+use server::rooms::{Color, Coord, RoomOptions, RoomService, SeatKind};
+use tokio::time::Instant;
+
+#[test]
+fn byo_yomi_consumes_periods_only_on_overrun() {
+  let control = TimeControl::ByoYomi {
+    main_secs: 10,
+    periods: 3,
+    period_secs: 5,
+  };
+  let mut clock = MatchClock::new(control).unwrap();
+  let t0 = Instant::now();
+  clock.start(Color::Black, t0);
+
+  // 12s: main time gone, finished inside the first period -> all periods kept.
+  assert!(clock.switch(Color::White, t0 + Duration::from_secs(12)));
+  let view = clock.view(t0 + Duration::from_secs(12));
+  assert_eq!(view.black.remaining_ms, 0);
+  assert_eq!(view.black.periods_left, Some(3));
+
+  // Black thinks 11s in byo-yomi: two periods used up, one left.
+  clock.start(Color::Black, t0 + Duration::from_secs(20));
+  assert!(clock.switch(Color::White, t0 + Duration::from_secs(31)));
+  assert_eq!(clock.view(t0 + Duration::from_secs(31)).black.periods_left, Some(1));
+
+  clock.start(Color::Black, t0 + Duration::from_secs(40));
+  assert_eq!(clock.flagged(t0 + Duration::from_secs(44)), None);
+  assert_eq!(clock.flagged(t0 + Duration::from_secs(45)), Some(Color::Black));
+}
+
+#[tokio::test(start_paused = true)]
+async fn fischer_flag_falls_without_a_move() {
+  let svc = RoomService::default();
+  let options = RoomOptions {
+    time_control: TimeControl::Fischer {
+      main_secs: 10,
+      increment_secs: 5,
+    },
+    ..Default::default()
+  };
+  let (room_id, _snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+
+  tokio::time::advance(Duration::from_secs(3)).await;
+  let (_room_id, _payload, events) = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  let moved = events.iter().find(|e| e.r#type == "match.moved").unwrap();
+  let black_ms = moved.payload["clocks"]["black"]["remainingMs"].as_u64();
+  assert_eq!(black_ms, Some(12_000));
+
+  tokio::time::advance(Duration::from_secs(9)).await;
+  assert!(svc.expire_clocks().await.is_empty());
+  tokio::time::advance(Duration::from_secs(2)).await;
+  let expired = svc.expire_clocks().await;
+  assert_eq!(expired.len(), 1);
+  let over = expired[0].1.iter().find(|e| e.r#type == "match.over").unwrap();
+  assert_eq!(over.payload.get("reason").and_then(|v| v.as_str()), Some("timeout"));
+  assert_eq!(over.payload.get("winner").and_then(|v| v.as_str()), Some("black"));
+}
+
+#[test]
+fn time_controls_are_bounded() {
+  let too_long = TimeControl::SuddenDeath { main_secs: u64::MAX / 10 };
+  assert_eq!(too_long.validate(), Err("invalid_time_control"));
+  let huge_periods = TimeControl::ByoYomi {
+    main_secs: 60,
+    periods: u32::MAX,
+    period_secs: 30,
+  };
+  assert!(huge_periods.validate().is_err());
+  let huge_increment = TimeControl::Fischer {
+    main_secs: 60,
+    increment_secs: u64::MAX,
+  };
+  assert!(huge_increment.validate().is_err());
+  assert!(TimeControl::ByoYomi { main_secs: 0, periods: 5, period_secs: 30 }.validate().is_ok());
+
+  // Even an unvalidated control must not overflow the clock arithmetic.
+  let control = TimeControl::ByoYomi {
+    main_secs: u64::MAX,
+    periods: u32::MAX,
+    period_secs: u64::MAX,
+  };
+  let mut clock = MatchClock::new(control).unwrap();
+  let t0 = Instant::now();
+  clock.start(Color::Black, t0);
+  assert_eq!(clock.flagged(t0 + Duration::from_secs(3600)), None);
+  assert!(clock.switch(Color::White, t0 + Duration::from_secs(3600)));
+}
@@ -0,0 +1,104 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use server::error::ApiError;
+use server::history::{decode_cursor, encode_cursor, paginate, MatchSummary};
+use std::cmp::Reverse;
+use uuid::Uuid;
+
+fn summary(id: u128, ended_at: DateTime<Utc>) -> MatchSummary {
+  MatchSummary {
+    match_id: Uuid::from_u128(id),
+    black: "alice".into(),
+    white: "bob".into(),
+    rule: "freestyle".into(),
+    board_size: 15,
+    result: "black_win".into(),
+    reason: "five".into(),
+    move_count: 9,
+    started_at: ended_at - Duration::minutes(10),
+    ended_at,
+  }
+}
+
+/// Mirrors the keyset query: rows strictly older than the cursor, newest first, `limit + 1` of them.
+fn fetch(all: &[MatchSummary], cursor: Option<&str>, limit: i64) -> Vec<MatchSummary> {
+  let cursor = cursor.map(|c| decode_cursor(c).unwrap());
+  all
+    .iter()
+    .filter(|m| cursor.is_none_or(|key| (m.ended_at, m.match_id) < key))
+    .take(limit as usize + 1)
+    .cloned()
+    .collect()
+}
+
+#[test]
+fn cursors_round_trip() {
+  let ended_at = Utc.with_ymd_and_hms(2024, 3, 9, 18, 30, 5).unwrap() + Duration::microseconds(123_456);
+  let id = Uuid::new_v4();
+  let (ts, decoded) = decode_cursor(&encode_cursor(ended_at, id)).unwrap();
+  assert_eq!(ts, ended_at);
+  assert_eq!(decoded, id);
+}
+
+#[test]
+fn malformed_cursors_are_bad_requests() {
+  let id = Uuid::new_v4();
+  let cases = [
+    "not base64!".to_string(),
+    URL_SAFE_NO_PAD.encode(format!("2024-03-09T18:30:05Z{id}")),
+    URL_SAFE_NO_PAD.encode(format!("yesterday|{id}")),
+    URL_SAFE_NO_PAD.encode("2024-03-09T18:30:05Z|not-a-uuid"),
+  ];
+  for cursor in cases {
+    assert!(matches!(decode_cursor(&cursor), Err(ApiError::BadRequest)), "{cursor}");
+  }
+}
+
+#[test]
+fn pages_stop_at_the_limit() {
+  let now = Utc.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap();
+  let rows: Vec<_> = (0..3).map(|i| summary(10 - i, now - Duration::hours(i as i64))).collect();
+
+  let page = paginate(rows.clone(), 3);
+  assert_eq!(page.items.len(), 3);
+  assert!(page.next_cursor.is_none());
+
+  let page = paginate(rows.clone(), 2);
+  assert_eq!(page.items.len(), 2);
+  let cursor = page.next_cursor.unwrap();
+  assert_eq!(cursor, encode_cursor(rows[1].ended_at, rows[1].match_id));
+
+  assert!(paginate(Vec::new(), 2).next_cursor.is_none());
+}
+
+#[test]
+fn keyset_pages_cover_every_match_once() {
+  let now = Utc.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap();
+  // Two pairs share an end time so the id tie-break has to carry across page boundaries.
+  let mut all = vec![
+    summary(7, now),
+    summary(6, now),
+    summary(5, now - Duration::seconds(1)),
+    summary(9, now - Duration::minutes(1)),
+    summary(4, now - Duration::minutes(1)),
+    summary(1, now - Duration::hours(1)),
+    summary(3, now - Duration::days(1)),
+  ];
+  all.sort_by_key(|m| Reverse((m.ended_at, m.match_id)));
+
+  for limit in 1..=all.len() as i64 + 1 {
+    let mut seen = Vec::new();
+    let mut cursor = None;
+    loop {
+      let page = paginate(fetch(&all, cursor.as_deref(), limit), limit);
+      assert!(page.items.len() as i64 <= limit);
+      seen.extend(page.items.iter().map(|m| m.match_id));
+      match page.next_cursor {
+        Some(next) => cursor = Some(next),
+        None => break,
+      }
+    }
+    let expected: Vec<_> = all.iter().map(|m| m.match_id).collect();
+    assert_eq!(seen, expected, "limit {limit}");
+  }
+}
@@ -0,0 +1,18 @@
+use chrono::{TimeZone, Utc};
+use server::leaderboard::{Period, TieMode};
+
+#[test]
+fn periods_start_at_calendar_boundaries() {
+  // Thursday 2024-02-15 13:45 UTC.
+  let now = Utc.with_ymd_and_hms(2024, 2, 15, 13, 45, 0).unwrap();
+  assert_eq!(Period::All.start(now), None);
+  assert_eq!(Period::Month.start(now), Some(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap()));
+  assert_eq!(Period::Week.start(now), Some(Utc.with_ymd_and_hms(2024, 2, 12, 0, 0, 0).unwrap()));
+}
+
+#[test]
+fn tie_modes_parse_from_config() {
+  assert_eq!("dense".parse(), Ok(TieMode::Dense));
+  assert_eq!("unique".parse(), Ok(TieMode::Unique));
+  assert!("bogus".parse::<TieMode>().is_err());
+}
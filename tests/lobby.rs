@@ -0,0 +1,36 @@
+use server::rooms::{LobbyEvent, RoomService, RoomState, SeatKind};
+
+#[tokio::test]
+async fn lobby_deltas_follow_room_lifecycle() {
+  let svc = RoomService::default();
+  let mut rx = svc.subscribe_lobby();
+
+  let (room_id, _snap) = svc.create_room("alice", "open game".to_string()).await;
+  match rx.try_recv().unwrap() {
+    LobbyEvent::Added(room) => {
+      assert_eq!(room.title, "open game");
+      assert_eq!(room.black.as_deref(), Some("alice"));
+    }
+    other => panic!("unexpected {other:?}"),
+  }
+
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  match rx.try_recv().unwrap() {
+    LobbyEvent::Updated(room) => assert_eq!(room.spectator_count, 1),
+    other => panic!("unexpected {other:?}"),
+  }
+
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+  let list = svc.lobby_list().await;
+  assert_eq!(list.len(), 1);
+  assert!(matches!(list[0].state, RoomState::Playing));
+  assert_eq!(list[0].white.as_deref(), Some("bob"));
+
+  let _ = svc.leave_room("bob").await;
+  let _ = svc.leave_room("alice").await;
+  let last = std::iter::from_fn(|| rx.try_recv().ok()).last().unwrap();
+  assert!(matches!(last, LobbyEvent::Removed(id) if id == room_id));
+  assert!(svc.lobby_list().await.is_empty());
+}
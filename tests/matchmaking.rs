@@ -0,0 +1,92 @@
+use server::matchmaking::Matchmaker;
+use server::ratings::Rating;
+use server::rooms::{RoomOptions, RoomService};
+use tokio::time::{Duration, Instant};
+
+#[test]
+fn rating_window_widens_while_waiting() {
+  let mm = Matchmaker::default();
+  let t0 = Instant::now();
+  mm.enqueue("alice", 1500.0, RoomOptions::default(), t0);
+  mm.enqueue("bob", 1800.0, RoomOptions::default(), t0);
+  assert!(mm.pair(t0).is_empty());
+
+  // 300 points apart: both windows must have grown past the gap.
+  let pairs = mm.pair(t0 + Duration::from_secs(20));
+  assert_eq!(pairs.len(), 1);
+  assert_eq!((pairs[0].first.as_str(), pairs[0].second.as_str()), ("alice", "bob"));
+  assert!(!mm.is_queued("alice") && !mm.is_queued("bob"));
+}
+
+#[test]
+fn closest_compatible_opponent_is_chosen() {
+  let mm = Matchmaker::default();
+  let t0 = Instant::now();
+  let rated = RoomOptions {
+    rated: true,
+    ..Default::default()
+  };
+  mm.enqueue("alice", 1500.0, RoomOptions::default(), t0);
+  mm.enqueue("carol", 1510.0, rated, t0);
+  mm.enqueue("bob", 1580.0, RoomOptions::default(), t0);
+  mm.enqueue("dave", 1540.0, RoomOptions::default(), t0);
+
+  let pairs = mm.pair(t0);
+  assert_eq!(pairs.len(), 1);
+  assert_eq!((pairs[0].first.as_str(), pairs[0].second.as_str()), ("alice", "dave"));
+  assert!(mm.is_queued("carol") && mm.is_queued("bob"));
+
+  assert!(mm.cancel("carol"));
+  assert!(!mm.cancel("carol"));
+}
+
+#[test]
+fn skipped_pairs_can_be_requeued() {
+  let mm = Matchmaker::default();
+  let t0 = Instant::now();
+  mm.enqueue("alice", 1500.0, RoomOptions::default(), t0);
+  mm.enqueue("bob", 1800.0, RoomOptions::default(), t0);
+  let pairs = mm.pair(t0 + Duration::from_secs(20));
+  assert_eq!(pairs.len(), 1);
+
+  // Only bob goes back, with his original ticket; unknown players are ignored.
+  mm.requeue(&pairs[0], "bob");
+  mm.requeue(&pairs[0], "mallory");
+  assert!(mm.is_queued("bob") && !mm.is_queued("alice") && !mm.is_queued("mallory"));
+  let now = t0 + Duration::from_secs(20);
+  mm.enqueue("carol", 1760.0, RoomOptions::default(), now);
+  let pairs = mm.pair(now);
+  assert_eq!(pairs.len(), 1);
+  assert_eq!((pairs[0].first.as_str(), pairs[0].second.as_str()), ("bob", "carol"));
+}
+
+#[tokio::test]
+async fn paired_room_starts_immediately() {
+  let svc = RoomService::default();
+  let (room_id, snapshot, start) = svc
+    .create_paired_room("alice", "bob", "alice vs bob".to_string(), RoomOptions::default())
+    .await
+    .unwrap();
+  assert_eq!(snapshot.seats.black.unwrap().username, "alice");
+  assert_eq!(snapshot.seats.white.unwrap().username, "bob");
+  assert!(start.is_some());
+  assert!(svc.is_playing("bob").await);
+  assert_eq!(svc.room_id_for_user("bob"), Some(room_id));
+}
+
+#[tokio::test]
+async fn failed_pairing_leaves_no_room_behind() {
+  let svc = RoomService::default();
+  svc.set_rating("alice", Rating::default());
+  let rated = RoomOptions {
+    rated: true,
+    ..Default::default()
+  };
+  let res = svc
+    .create_paired_room("alice", "bob", "alice vs bob".to_string(), rated)
+    .await;
+  assert_eq!(res.err(), Some("rating_unavailable"));
+  assert_eq!(svc.room_id_for_user("alice"), None);
+  assert_eq!(svc.room_id_for_user("bob"), None);
+  assert!(svc.lobby_list().await.is_empty());
+}
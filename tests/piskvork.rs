@@ -0,0 +1,115 @@
+#![cfg(unix)]
+
+use std::{os::unix::fs::PermissionsExt, path::PathBuf, time::Duration};
+
+use chrono::Utc;
+use server::bots::{Engine, Position};
+use server::opening::OpeningRule;
+use server::piskvork::{EngineSpec, PiskvorkEngine};
+use server::rooms::{Color, Coord, Move};
+use server::rules::RuleSet;
+
+/// Writes an executable shell script standing in for an engine.
+fn fake_engine(body: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("pbrain-{}.sh", uuid::Uuid::new_v4()));
+  std::fs::write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+  std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+  path
+}
+
+fn spec(path: PathBuf, timeout_ms: u64) -> EngineSpec {
+  EngineSpec {
+    name: "fake".to_string(),
+    path,
+    timeout_turn: Duration::from_millis(timeout_ms),
+  }
+}
+
+fn position(moves: &[(Color, i32, i32)], to_move: Color) -> Position {
+  Position {
+    match_id: "m1".to_string(),
+    board_size: 15,
+    rule: RuleSet::Freestyle,
+    opening: OpeningRule::Free,
+    moves: moves
+      .iter()
+      .map(|&(color, row, col)| Move {
+        color,
+        coord: Coord { row, col },
+        played_at: Utc::now(),
+      })
+      .collect(),
+    to_move,
+    time_left_ms: None,
+  }
+}
+
+// Answers with a fixed point per command so the test can tell which one it was sent.
+const SCRIPTED: &str = r#"
+while read -r line; do
+  case "$line" in
+    START*) echo OK ;;
+    BEGIN) echo "MESSAGE thinking"; echo "7,7" ;;
+    TURN*) echo "1,2" ;;
+    DONE) echo "3,4" ;;
+    END) exit 0 ;;
+  esac
+done
+"#;
+
+#[tokio::test]
+async fn engine_gets_begin_turn_and_board() {
+  let mut engine = PiskvorkEngine::new(spec(fake_engine(SCRIPTED), 2000));
+
+  let c = engine.think(&position(&[], Color::Black)).await.unwrap();
+  assert_eq!((c.row, c.col), (7, 7));
+
+  // Only the opponent's reply is new: TURN x,y.
+  let pos = position(&[(Color::Black, 7, 7), (Color::White, 8, 8)], Color::Black);
+  let c = engine.think(&pos).await.unwrap();
+  assert_eq!((c.row, c.col), (2, 1));
+
+  // A takeback leaves the engine's view behind, so the whole board is resent.
+  let pos = position(&[(Color::Black, 7, 7)], Color::White);
+  let c = engine.think(&pos).await.unwrap();
+  assert_eq!((c.row, c.col), (4, 3));
+
+  engine.shutdown().await;
+}
+
+#[tokio::test]
+async fn engine_is_relaunched_for_a_new_match() {
+  let mut engine = PiskvorkEngine::new(spec(fake_engine(SCRIPTED), 2000));
+  engine.think(&position(&[], Color::Black)).await.unwrap();
+
+  // Same stones as a TURN would need, but a fresh process knows nothing and gets the board.
+  let next = Position {
+    match_id: "m2".to_string(),
+    ..position(&[(Color::Black, 7, 7), (Color::White, 8, 8)], Color::Black)
+  };
+  let c = engine.think(&next).await.unwrap();
+  assert_eq!((c.row, c.col), (4, 3));
+
+  engine.shutdown().await;
+}
+
+#[tokio::test]
+async fn slow_or_failing_engine_is_an_error() {
+  let slow = fake_engine("while read -r line; do case \"$line\" in START*) echo OK ;; BEGIN) sleep 5 ;; esac; done\n");
+  let mut engine = PiskvorkEngine::new(spec(slow, 100));
+  let started = std::time::Instant::now();
+  assert!(engine.think(&position(&[], Color::Black)).await.is_err());
+  assert!(started.elapsed() < Duration::from_secs(3));
+
+  let broken = fake_engine("while read -r line; do case \"$line\" in START*) echo OK ;; BEGIN) echo ERROR no ;; esac; done\n");
+  let mut engine = PiskvorkEngine::new(spec(broken, 1000));
+  assert!(engine.think(&position(&[], Color::Black)).await.is_err());
+}
+
+#[test]
+fn engine_list_parses() {
+  let list = EngineSpec::parse_list("pela=/opt/pela, embryo = ./embryo ,", Duration::from_secs(1)).unwrap();
+  let names: Vec<_> = list.iter().map(|e| e.name.as_str()).collect();
+  assert_eq!(names, ["pela", "embryo"]);
+  assert!(EngineSpec::parse_list("nopath", Duration::from_secs(1)).is_err());
+}
@@ -0,0 +1,108 @@
+use server::ratings::Rating;
+use server::rooms::{RoomOptions, RoomService, SeatKind};
+
+fn r(rating: f64, rd: f64) -> Rating {
+  Rating {
+    rating,
+    rd,
+    volatility: 0.06,
+  }
+}
+
+#[test]
+fn glicko2_matches_reference_example() {
+  // Worked example from Glickman's "Example of the Glicko-2 system".
+  let player = r(1500.0, 200.0);
+  let after = player.update(&[(r(1400.0, 30.0), 1.0), (r(1550.0, 100.0), 0.0), (r(1700.0, 300.0), 0.0)]);
+  assert!((after.rating - 1464.06).abs() < 0.01, "{after:?}");
+  assert!((after.rd - 151.52).abs() < 0.01, "{after:?}");
+  assert!((after.volatility - 0.05999).abs() < 0.00001, "{after:?}");
+
+  // Sitting out a period only widens the deviation.
+  let idle = player.update(&[]);
+  assert_eq!(idle.rating, 1500.0);
+  assert!(idle.rd > 200.0);
+}
+
+async fn play_and_resign(svc: &RoomService, rated: bool) {
+  let options = RoomOptions {
+    rated,
+    ..Default::default()
+  };
+  let (room_id, _snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+  let _ = svc.match_resign("bob").await.unwrap();
+}
+
+#[tokio::test]
+async fn only_rated_rooms_change_ratings() {
+  let svc = RoomService::default();
+  svc.set_rating("alice", Rating::default());
+  svc.set_rating("bob", Rating::default());
+
+  play_and_resign(&svc, false).await;
+  assert_eq!(svc.rating("alice"), Some(Rating::default()));
+
+  let _ = svc.leave_room("bob").await;
+  let _ = svc.leave_room("alice").await;
+  play_and_resign(&svc, true).await;
+  let alice = svc.rating("alice").unwrap();
+  let bob = svc.rating("bob").unwrap();
+  assert!(alice.rating > 1500.0 && bob.rating < 1500.0);
+  assert!((alice.rating - 1500.0 + bob.rating - 1500.0).abs() < 0.01);
+
+  let room_id = svc.room_id_for_user("alice").unwrap();
+  let snap = svc.snapshot(room_id).await.unwrap();
+  assert!(snap.rated);
+  assert_eq!(snap.seats.black.unwrap().rating, Some(alice));
+}
+
+#[tokio::test]
+async fn rated_matches_need_known_ratings() {
+  let svc = RoomService::default();
+  svc.set_rating("alice", Rating::default());
+  let options = RoomOptions {
+    rated: true,
+    ..Default::default()
+  };
+  let (room_id, _snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  assert_eq!(svc.set_ready("bob", true).await.err(), Some("rating_unavailable"));
+  assert!(svc.set_ready("bob", false).await.is_ok());
+
+  svc.set_rating("bob", r(1800.0, 60.0));
+  let (_, _, start) = svc.set_ready("bob", true).await.unwrap();
+  assert!(start.is_some());
+}
+
+#[tokio::test]
+async fn cached_ratings_survive_reconnects_and_go_with_the_player() {
+  let svc = RoomService::default();
+  svc.set_rating("alice", r(1600.0, 80.0));
+  // A reconnect reads the database, which may still hold the pre-game value.
+  svc.seed_rating("alice", r(1500.0, 80.0));
+  assert_eq!(svc.rating("alice"), Some(r(1600.0, 80.0)));
+  svc.seed_rating("bob", r(1450.0, 90.0));
+  assert_eq!(svc.rating("bob"), Some(r(1450.0, 90.0)));
+
+  let _ = svc.create_room("alice", "t".to_string()).await;
+  svc.forget_rating("alice");
+  svc.forget_rating("bob");
+  assert!(svc.rating("alice").is_some());
+  assert!(svc.rating("bob").is_none());
+
+  let _ = svc.leave_room("alice").await;
+  svc.forget_rating("alice");
+  assert!(svc.rating("alice").is_none());
+}
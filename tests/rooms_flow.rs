@@ -1,4 +1,5 @@
-use server::rooms::{Coord, RoomService, SeatKind};
+use server::opening::{OpeningChoice, OpeningRule};
+use server::rooms::{Coord, RoomOptions, RoomService, SeatKind, Visibility};
 
 
 #[tokio::test]
@@ -75,3 +76,434 @@ async fn win_by_moves_emits_match_over() {
     assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
   }
 }
+
+#[tokio::test]
+async fn board_size_is_per_room() {
+  let svc = RoomService::default();
+  let too_big = RoomOptions {
+    board_size: 30,
+    ..Default::default()
+  };
+  assert_eq!(
+    svc.create_room_with_options("alice", "t".to_string(), too_big).await.err(),
+    Some("invalid_board_size")
+  );
+
+  let small = RoomOptions {
+    board_size: 9,
+    ..Default::default()
+  };
+  let (room_id, snap) = svc
+    .create_room_with_options("alice", "t".to_string(), small)
+    .await
+    .unwrap();
+  assert_eq!(snap.board_size, 9);
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let (_room_id, _snap, start_evt) = svc.set_ready("bob", true).await.unwrap();
+  let start_evt = start_evt.unwrap();
+  assert_eq!(start_evt.payload.get("boardSize").and_then(|v| v.as_u64()), Some(9));
+
+  let (_room_id, payload, _events) = svc
+    .match_move("alice", Coord { row: 9, col: 0 })
+    .await
+    .unwrap();
+  assert_eq!(payload.get("reason").and_then(|v| v.as_str()), Some("out_of_range"));
+  let (_room_id, payload, _events) = svc
+    .match_move("alice", Coord { row: 8, col: 8 })
+    .await
+    .unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+}
+
+#[tokio::test]
+async fn swap2_choice_reassigns_seats() {
+  let svc = RoomService::default();
+  let options = RoomOptions {
+    opening: OpeningRule::Swap2,
+    ..Default::default()
+  };
+  let (room_id, _snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+
+  // Alice places black, white, black.
+  for (row, col) in [(7, 7), (7, 8), (8, 8)] {
+    let (_room_id, payload, _events) = svc.match_move("alice", Coord { row, col }).await.unwrap();
+    assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+  }
+  let (_room_id, payload, _events) = svc
+    .match_move("bob", Coord { row: 0, col: 0 })
+    .await
+    .unwrap();
+  assert_eq!(
+    payload.get("reason").and_then(|v| v.as_str()),
+    Some("opening_choice_pending")
+  );
+  assert!(svc.match_opening_choice("alice", OpeningChoice::Black).await.is_err());
+
+  let (_room_id, _payload, _events) = svc
+    .match_opening_choice("bob", OpeningChoice::Black)
+    .await
+    .unwrap();
+  let snap = svc.snapshot(room_id).await.unwrap();
+  assert_eq!(snap.seats.black.as_ref().map(|s| s.username.as_str()), Some("bob"));
+  assert_eq!(snap.seats.white.as_ref().map(|s| s.username.as_str()), Some("alice"));
+  assert!(snap.current_match.as_ref().unwrap().opening_phase.is_none());
+
+  // Fourth stone is white, now played by Alice.
+  let (_room_id, payload, _events) = svc
+    .match_move("alice", Coord { row: 6, col: 6 })
+    .await
+    .unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+}
+
+#[tokio::test]
+async fn pro_opening_restricts_black_second_stone() {
+  let svc = RoomService::default();
+  let options = RoomOptions {
+    opening: OpeningRule::Pro,
+    ..Default::default()
+  };
+  let (room_id, snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  assert_eq!(snap.opening_restriction.as_ref().map(|r| r.min_distance), Some(3));
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+
+  let (_room_id, payload, _events) = svc.match_move("alice", Coord { row: 0, col: 0 }).await.unwrap();
+  assert_eq!(payload.get("reason").and_then(|v| v.as_str()), Some("opening_restricted"));
+  let (_room_id, payload, _events) = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+  let (_room_id, payload, _events) = svc.match_move("bob", Coord { row: 7, col: 8 }).await.unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+
+  let (_room_id, payload, _events) = svc.match_move("alice", Coord { row: 9, col: 9 }).await.unwrap();
+  assert_eq!(payload.get("reason").and_then(|v| v.as_str()), Some("opening_restricted"));
+  let (_room_id, payload, _events) = svc.match_move("alice", Coord { row: 10, col: 7 }).await.unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+}
+
+async fn start_match(svc: &RoomService, options: RoomOptions) -> uuid::Uuid {
+  let (room_id, _snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+  room_id
+}
+
+fn over_reason(events: &[server::protocol::EnvelopeOut]) -> Option<String> {
+  events
+    .iter()
+    .find(|e| e.r#type == "match.over")
+    .and_then(|e| e.payload.get("reason").and_then(|v| v.as_str()).map(str::to_string))
+}
+
+#[tokio::test]
+async fn resign_and_draw_agreement() {
+  let svc = RoomService::default();
+  let _ = start_match(&svc, RoomOptions::default()).await;
+  let (_room_id, _payload, events) = svc.match_resign("bob").await.unwrap();
+  assert_eq!(over_reason(&events).as_deref(), Some("resign"));
+  assert!(svc.match_resign("bob").await.is_err());
+
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+  let _ = svc.match_offer_draw("alice").await.unwrap();
+  assert!(svc.match_respond_draw("alice", true).await.is_err());
+
+  // Bob plays on instead of answering, so the offer lapses.
+  let _ = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  let (_room_id, _payload, events) = svc.match_move("bob", Coord { row: 7, col: 8 }).await.unwrap();
+  assert!(events.iter().any(|e| e.r#type == "match.drawExpired"));
+  assert!(svc.match_respond_draw("bob", true).await.is_err());
+
+  let _ = svc.match_offer_draw("alice").await.unwrap();
+  let (_room_id, _payload, events) = svc.match_respond_draw("bob", true).await.unwrap();
+  assert_eq!(over_reason(&events).as_deref(), Some("agreement"));
+}
+
+#[tokio::test]
+async fn undo_returns_turn_to_requester() {
+  let svc = RoomService::default();
+  let _ = start_match(&svc, RoomOptions::default()).await;
+  let _ = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  let _ = svc.match_move("bob", Coord { row: 7, col: 8 }).await.unwrap();
+
+  // Alice asks after Bob replied: both stones come off and it's Alice's move again.
+  let _ = svc.match_request_undo("alice").await.unwrap();
+  let (_room_id, _payload, events) = svc.match_respond_undo("bob", true).await.unwrap();
+  let undone = events.iter().find(|e| e.r#type == "match.undone").unwrap();
+  assert_eq!(undone.payload.get("moves").and_then(|v| v.as_array()).map(|a| a.len()), Some(0));
+  assert_eq!(undone.payload.get("turn").and_then(|v| v.as_str()), Some("black"));
+
+  let (_room_id, payload, _events) = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+
+  let _ = svc.match_request_undo("alice").await.unwrap();
+  let _ = svc.match_respond_undo("bob", false).await.unwrap();
+  let (_room_id, payload, _events) = svc.match_move("bob", Coord { row: 7, col: 8 }).await.unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+}
+
+#[tokio::test]
+async fn pending_requests_lapse_with_the_position() {
+  let svc = RoomService::default();
+  let room_id = start_match(&svc, RoomOptions::default()).await;
+  let _ = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+
+  // Bob plays on instead of answering Alice's takeback request.
+  let _ = svc.match_request_undo("alice").await.unwrap();
+  let (_room_id, _payload, events) = svc.match_move("bob", Coord { row: 7, col: 8 }).await.unwrap();
+  assert!(events.iter().any(|e| e.r#type == "match.undoExpired"));
+  assert!(svc.match_state(room_id).await.unwrap().undo_request.is_none());
+
+  // An accepted takeback withdraws a draw offer made before it.
+  let _ = svc.match_offer_draw("alice").await.unwrap();
+  let _ = svc.match_request_undo("bob").await.unwrap();
+  let (_room_id, _payload, events) = svc.match_respond_undo("alice", true).await.unwrap();
+  assert!(events.iter().any(|e| e.r#type == "match.drawExpired"));
+  assert!(svc.match_state(room_id).await.unwrap().draw_offer.is_none());
+}
+
+#[tokio::test]
+async fn takeback_can_be_disabled() {
+  let svc = RoomService::default();
+  let options = RoomOptions {
+    allow_takeback: false,
+    ..Default::default()
+  };
+  let _ = start_match(&svc, options).await;
+  let _ = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  assert_eq!(
+    svc.match_request_undo("alice").await.err().map(|e| e.0),
+    Some("takeback_disabled")
+  );
+}
+
+#[tokio::test(start_paused = true)]
+async fn disconnected_seat_is_held_then_abandoned() {
+  let svc = RoomService::default();
+  let room_id = start_match(&svc, RoomOptions::default()).await;
+  let grace = std::time::Duration::from_secs(30);
+
+  let (_room_id, events) = svc.hold_seat("bob", grace).await.unwrap();
+  assert!(events.iter().any(|e| e.r#type == "match.opponentDisconnected"));
+  let snap = svc.snapshot(room_id).await.unwrap();
+  assert_eq!(snap.seats.white.as_ref().map(|s| s.connected), Some(false));
+
+  // Back in time: seat and match are intact.
+  let (_room_id, events) = svc.resume_seat("bob").await.unwrap();
+  assert!(events.iter().any(|e| e.r#type == "match.opponentReconnected"));
+  assert!(svc.match_state(room_id).await.is_some());
+
+  let _ = svc.hold_seat("bob", grace).await.unwrap();
+  tokio::time::advance(std::time::Duration::from_secs(31)).await;
+  let expired = svc.expire_held_seats().await;
+  assert_eq!(expired.len(), 1);
+  assert_eq!(over_reason(&expired[0].1).as_deref(), Some("abandoned"));
+  let snap = svc.snapshot(room_id).await.unwrap();
+  assert!(snap.seats.white.is_none());
+  assert!(svc.room_id_for_user("bob").is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn match_abandoned_by_both_players_is_drawn() {
+  let svc = RoomService::default();
+  let room_id = start_match(&svc, RoomOptions::default()).await;
+  let grace = std::time::Duration::from_secs(30);
+
+  let _ = svc.hold_seat("alice", grace).await.unwrap();
+  let _ = svc.hold_seat("bob", grace).await.unwrap();
+  tokio::time::advance(std::time::Duration::from_secs(31)).await;
+  let expired = svc.expire_held_seats().await;
+  assert_eq!(expired.len(), 1);
+  let over = expired[0].1.iter().find(|e| e.r#type == "match.over").unwrap();
+  assert_eq!(over.payload["result"], "draw");
+  assert_eq!(over.payload["reason"], "abandoned");
+  assert!(svc.room_id_for_user("alice").is_none() && svc.room_id_for_user("bob").is_none());
+  assert!(svc.snapshot(room_id).await.is_none());
+}
+
+#[tokio::test]
+async fn late_spectator_sees_running_match() {
+  let svc = RoomService::default();
+  let room_id = start_match(&svc, RoomOptions::default()).await;
+  let _ = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  let _ = svc.match_move("bob", Coord { row: 8, col: 8 }).await.unwrap();
+
+  let snap = svc.join_room("carol", room_id).await.unwrap();
+  let m = snap.current_match.expect("snapshot carries the running match");
+  assert_eq!(m.moves.len(), 2);
+  assert_eq!(m.turn, server::rooms::Color::Black);
+  assert_eq!(m.board_size, 15);
+}
+
+#[tokio::test]
+async fn finished_match_is_handed_to_history() {
+  let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+  let svc = RoomService::with_history(tx);
+  let room_id = start_match(&svc, RoomOptions::default()).await;
+  let _ = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  let _ = svc.match_move("bob", Coord { row: 8, col: 8 }).await.unwrap();
+  let _ = svc.match_resign("bob").await.unwrap();
+
+  let m = rx.try_recv().expect("finished match recorded");
+  assert_eq!(m.room_id, room_id);
+  assert_eq!((m.black.as_str(), m.white.as_str()), ("alice", "bob"));
+  assert_eq!(m.result(), "black_win");
+  assert_eq!(m.reason, "resign");
+  assert_eq!(m.moves.len(), 2);
+  assert!(m.started_at <= m.moves[0].played_at && m.moves[1].played_at <= m.ended_at);
+}
+
+#[tokio::test]
+async fn private_room_needs_password_or_invite() {
+  let svc = RoomService::default();
+  let options = RoomOptions {
+    visibility: Visibility::Private,
+    password: Some("hunter2".to_string()),
+    ..Default::default()
+  };
+  let (room_id, snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  assert!(snap.has_password);
+  assert!(svc.lobby_list().await.is_empty());
+
+  assert_eq!(svc.join_room("bob", room_id).await.err(), Some("room_locked"));
+  assert_eq!(
+    svc.join_room_with_credential("bob", room_id, &["wrong"]).await.err(),
+    Some("room_locked")
+  );
+  assert!(svc.join_room_with_credential("bob", room_id, &["hunter2"]).await.is_ok());
+
+  assert_eq!(svc.create_invite("bob").await.err(), Some("forbidden"));
+  let (_, code) = svc.create_invite("alice").await.unwrap();
+  assert!(svc.join_room_with_credential("carol", room_id, &[&code]).await.is_ok());
+
+  svc.revoke_invite("alice").await.unwrap();
+  assert_eq!(
+    svc.join_room_with_credential("dave", room_id, &[&code]).await.err(),
+    Some("room_locked")
+  );
+}
+
+#[tokio::test(start_paused = true)]
+async fn credentials_are_tried_separately_and_rate_limited() {
+  let svc = RoomService::default();
+  let options = RoomOptions {
+    visibility: Visibility::Private,
+    password: Some("hunter2".to_string()),
+    ..Default::default()
+  };
+  let (room_id, _snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  let (_, code) = svc.create_invite("alice").await.unwrap();
+
+  // A stale password next to a good invite code still gets in.
+  assert!(svc.join_room_with_credential("bob", room_id, &["wrong", &code]).await.is_ok());
+
+  for _ in 0..5 {
+    assert_eq!(
+      svc.join_room_with_credential("mallory", room_id, &["guess!"]).await.err(),
+      Some("room_locked")
+    );
+  }
+  assert_eq!(
+    svc.join_room_with_credential("mallory", room_id, &[&code]).await.err(),
+    Some("rate_limited")
+  );
+  // Other users and public rooms are unaffected.
+  assert!(svc.join_room_with_credential("carol", room_id, &[&code]).await.is_ok());
+  let (public_id, _snap) = svc.create_room("dave", "p".to_string()).await;
+  assert!(svc.join_room("mallory", public_id).await.is_ok());
+
+  tokio::time::advance(std::time::Duration::from_secs(61)).await;
+  assert!(svc.join_room_with_credential("mallory", room_id, &[&code]).await.is_ok());
+}
+
+#[tokio::test]
+async fn owner_moderates_and_hands_over_on_leave() {
+  let svc = RoomService::default();
+  let (room_id, snap) = svc.create_room("alice", "t".to_string()).await;
+  assert_eq!(snap.owner, "alice");
+  for u in ["bob", "carol", "dave"] {
+    let _ = svc.join_room(u, room_id).await.unwrap();
+  }
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+
+  assert_eq!(svc.kick("bob", "carol").await.err(), Some("forbidden"));
+  let (_, snap) = svc.kick("alice", "carol").await.unwrap();
+  assert!(!snap.spectators.contains(&"carol".to_string()));
+  assert_eq!(svc.room_id_for_user("carol"), None);
+  // Kicked, not banned: carol may come back.
+  let _ = svc.join_room("carol", room_id).await.unwrap();
+
+  let (_, _, removed) = svc.ban("alice", "dave").await.unwrap();
+  assert!(removed);
+  assert_eq!(svc.join_room("dave", room_id).await.err(), Some("banned"));
+
+  // Seated players can't be removed mid-game; spectators can.
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+  assert_eq!(svc.kick("alice", "bob").await.err(), Some("invalid_room_state"));
+  let _ = svc.kick("alice", "carol").await.unwrap();
+  assert!(svc.is_playing("bob").await);
+
+  let (_, snap) = svc.transfer_owner("alice", "bob").await.unwrap();
+  assert_eq!(snap.owner, "bob");
+  let (_, snap) = svc.transfer_owner("bob", "alice").await.unwrap();
+  assert_eq!(snap.owner, "alice");
+
+  let _ = svc.leave_room("alice").await;
+  assert_eq!(svc.snapshot(room_id).await.unwrap().owner, "bob");
+}
+
+#[tokio::test]
+async fn rematch_swaps_colors_and_keeps_score() {
+  let svc = RoomService::default();
+  let room_id = start_match(&svc, RoomOptions::default()).await;
+  assert!(svc.match_rematch("alice").await.is_err());
+  let _ = svc.match_resign("bob").await.unwrap();
+
+  let (_, payload, events) = svc.match_rematch("bob").await.unwrap();
+  assert_eq!(payload.get("started").and_then(|v| v.as_bool()), Some(false));
+  assert!(events.iter().any(|e| e.r#type == "match.rematchRequested"));
+  assert_eq!(
+    svc.match_rematch("bob").await.err().map(|e| e.0),
+    Some("rematch_already_requested")
+  );
+
+  let (_, payload, events) = svc.match_rematch("alice").await.unwrap();
+  assert_eq!(payload.get("started").and_then(|v| v.as_bool()), Some(true));
+  assert!(events.iter().any(|e| e.r#type == "match.start"));
+  let snap = svc.snapshot(room_id).await.unwrap();
+  assert_eq!(snap.seats.black.as_ref().map(|s| s.username.as_str()), Some("bob"));
+  assert_eq!(snap.seats.white.as_ref().map(|s| s.username.as_str()), Some("alice"));
+
+  // Bob, now Black, resigns again; the series follows players, not colors.
+  let _ = svc.match_resign("bob").await.unwrap();
+  let series = svc.snapshot(room_id).await.unwrap().series.unwrap();
+  let alice = series.players.iter().find(|p| p.username == "alice").unwrap();
+  assert_eq!(alice.wins, 2);
+  assert_eq!(series.draws, 0);
+}
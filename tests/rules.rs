@@ -0,0 +1,121 @@
+use server::rooms::{Coord, RoomOptions, RoomService, SeatKind};
+use server::board::{Board, BLACK, WHITE};
+use server::rules::{self, Forbidden, ForbiddenPolicy, RuleSet};
+
+fn board_with(stones: &[(usize, usize, u8)]) -> Board {
+  let mut b: Board = Default::default();
+  for &(r, c, v) in stones {
+    b[r][c] = v;
+  }
+  b
+}
+
+#[test]
+fn renju_forbidden_points() {
+  // Double three: open twos on a row and a column meeting at (7,7).
+  let b = board_with(&[(7, 5, BLACK), (7, 6, BLACK), (5, 7, BLACK), (6, 7, BLACK)]);
+  assert_eq!(rules::renju_forbidden(&b, 7, 7), Some(Forbidden::DoubleThree));
+
+  // Same shape but one three is capped by White, so it's only a three-and-a-dead-three.
+  let b = board_with(&[
+    (7, 5, BLACK),
+    (7, 6, BLACK),
+    (7, 4, WHITE),
+    (5, 7, BLACK),
+    (6, 7, BLACK),
+  ]);
+  assert_eq!(rules::renju_forbidden(&b, 7, 7), None);
+
+  // Double four in a single line: X.XXX.X with the middle stone missing.
+  let b = board_with(&[(7, 3, BLACK), (7, 5, BLACK), (7, 6, BLACK), (7, 9, BLACK)]);
+  assert_eq!(rules::renju_forbidden(&b, 7, 7), Some(Forbidden::DoubleFour));
+
+  // Overline.
+  let b = board_with(&[(7, 2, BLACK), (7, 3, BLACK), (7, 4, BLACK), (7, 6, BLACK), (7, 7, BLACK)]);
+  assert_eq!(rules::renju_forbidden(&b, 7, 5), Some(Forbidden::Overline));
+
+  // An exact five wins even when it also makes a double three elsewhere.
+  let b = board_with(&[
+    (7, 3, BLACK),
+    (7, 4, BLACK),
+    (7, 5, BLACK),
+    (7, 6, BLACK),
+    (5, 7, BLACK),
+    (6, 7, BLACK),
+    (5, 5, BLACK),
+    (6, 6, BLACK),
+  ]);
+  assert_eq!(rules::renju_forbidden(&b, 7, 7), None);
+}
+
+#[tokio::test]
+async fn renju_room_rejects_black_double_three() {
+  let svc = RoomService::default();
+  let options = RoomOptions {
+    rule: RuleSet::Renju,
+    forbidden_policy: ForbiddenPolicy::Reject,
+    ..Default::default()
+  };
+  let (room_id, _snap) = svc
+    .create_room_with_options("alice", "t".to_string(), options)
+    .await
+    .unwrap();
+  let _ = svc.join_room("bob", room_id).await.unwrap();
+  let _ = svc.take_seat("bob", SeatKind::White).await.unwrap();
+  let _ = svc.set_ready("alice", true).await.unwrap();
+  let _ = svc.set_ready("bob", true).await.unwrap();
+
+  let black = [(7, 5), (7, 6), (5, 7), (6, 7)];
+  for (i, (row, col)) in black.iter().enumerate() {
+    let (_, payload, _) = svc.match_move("alice", Coord { row: *row, col: *col }).await.unwrap();
+    assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+    let (_, payload, _) = svc.match_move("bob", Coord { row: 0, col: i as i32 * 2 }).await.unwrap();
+    assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(true));
+  }
+
+  let (_, payload, events) = svc.match_move("alice", Coord { row: 7, col: 7 }).await.unwrap();
+  assert_eq!(payload.get("accepted").and_then(|v| v.as_bool()), Some(false));
+  assert_eq!(payload.get("reason").and_then(|v| v.as_str()), Some("forbidden_33"));
+  assert!(events.is_empty());
+}
+
+#[test]
+fn win_checks_per_variant() {
+  // Six in a row for Black on row 7, cols 2..=7.
+  let six = board_with(&[
+    (7, 2, BLACK),
+    (7, 3, BLACK),
+    (7, 4, BLACK),
+    (7, 5, BLACK),
+    (7, 6, BLACK),
+    (7, 7, BLACK),
+  ]);
+  assert!(RuleSet::Freestyle.win_check().is_win(&six, 7, 7, BLACK));
+  assert!(!RuleSet::Standard.win_check().is_win(&six, 7, 7, BLACK));
+  assert!(!RuleSet::Renju.win_check().is_win(&six, 7, 7, BLACK));
+
+  // White overline still wins under Renju.
+  let six_white = board_with(&[
+    (7, 2, WHITE),
+    (7, 3, WHITE),
+    (7, 4, WHITE),
+    (7, 5, WHITE),
+    (7, 6, WHITE),
+    (7, 7, WHITE),
+  ]);
+  assert!(RuleSet::Renju.win_check().is_win(&six_white, 7, 7, WHITE));
+
+  // Caro: a five capped by White at both ends doesn't win; capped at one end it does.
+  let mut five = board_with(&[
+    (7, 3, BLACK),
+    (7, 4, BLACK),
+    (7, 5, BLACK),
+    (7, 6, BLACK),
+    (7, 7, BLACK),
+    (7, 2, WHITE),
+  ]);
+  assert!(RuleSet::Caro.win_check().is_win(&five, 7, 7, BLACK));
+  five[7][8] = WHITE;
+  assert!(!RuleSet::Caro.win_check().is_win(&five, 7, 7, BLACK));
+  assert!(RuleSet::Freestyle.win_check().is_win(&five, 7, 7, BLACK));
+}
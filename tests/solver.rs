@@ -0,0 +1,140 @@
+use server::board::{Board, BLACK, EMPTY, WHITE};
+use server::rooms::{Color, Coord};
+use server::rules::{self, RuleSet};
+use server::solver::{self, Kind, Limits, Verdict};
+use std::time::Duration;
+
+fn board(black: &[(usize, usize)], white: &[(usize, usize)]) -> Board {
+  let mut b = Board::new(15);
+  for &(r, c) in black {
+    b[r][c] = BLACK;
+  }
+  for &(r, c) in white {
+    b[r][c] = WHITE;
+  }
+  b
+}
+
+fn at(line: &[Coord], i: usize) -> (i32, i32) {
+  (line[i].row, line[i].col)
+}
+
+/// Replays `line` and checks it is a legal sequence ending in the attacker's five, with every
+/// attacking move before it a four when `kind` is VCF.
+fn assert_wins(mut b: Board, attacker: Color, rule: RuleSet, kind: Kind, line: &[Coord]) {
+  let (a, d) = match attacker {
+    Color::Black => (BLACK, WHITE),
+    Color::White => (WHITE, BLACK),
+  };
+  for (i, mv) in line.iter().enumerate() {
+    let (r, c) = (mv.row as usize, mv.col as usize);
+    let v = if i % 2 == 0 { a } else { d };
+    assert_eq!(b[r][c], EMPTY, "move {i} on an occupied point");
+    if rule == RuleSet::Renju && v == BLACK {
+      assert!(rules::renju_forbidden(&b, r, c).is_none(), "move {i} is forbidden");
+    }
+    b[r][c] = v;
+    let won = rule.win_check().is_win(&b, r, c, v);
+    if i + 1 == line.len() {
+      assert!(won, "line doesn't end in five");
+    } else {
+      assert!(!won, "move {i} already wins");
+      if kind == Kind::Vcf && v == a {
+        let four = (0..15).any(|rr| {
+          (0..15).any(|cc| {
+            b[rr][cc] == EMPTY && {
+              b[rr][cc] = a;
+              let five = rule.win_check().is_win(&b, rr, cc, a);
+              b[rr][cc] = EMPTY;
+              five
+            }
+          })
+        });
+        assert!(four, "move {i} is not a four");
+      }
+    }
+  }
+}
+
+#[test]
+fn finds_vcf_through_a_four_three() {
+  // Blocked three on row 7 and two on column 10: (7, 10) makes a four and an open three at once.
+  let b = board(&[(7, 7), (7, 8), (7, 9), (8, 10), (9, 10)], &[(7, 6), (0, 0), (0, 14), (14, 0), (14, 14)]);
+  let s = solver::solve(&b, Color::Black, RuleSet::Freestyle, Kind::Vcf, Limits::for_kind(Kind::Vcf));
+  assert_eq!(s.verdict, Verdict::Win);
+  assert_eq!(at(&s.line, 0), (7, 10));
+  assert_eq!(at(&s.line, 1), (7, 11));
+  assert_eq!(s.line.len(), 5);
+  assert_wins(b.clone(), Color::Black, RuleSet::Freestyle, Kind::Vcf, &s.line);
+
+  let s = solver::solve(&b, Color::White, RuleSet::Freestyle, Kind::Vcf, Limits::for_kind(Kind::Vcf));
+  assert_eq!(s.verdict, Verdict::NoWin);
+  assert!(s.line.is_empty());
+}
+
+#[test]
+fn vct_uses_threes_that_vcf_cannot() {
+  // Two open twos meeting at (7, 9): a double three.
+  let b = board(&[(7, 7), (7, 8), (8, 9), (9, 9)], &[(0, 0), (0, 14), (14, 0), (14, 14)]);
+  let vcf = solver::solve(&b, Color::Black, RuleSet::Freestyle, Kind::Vcf, Limits::for_kind(Kind::Vcf));
+  assert_eq!(vcf.verdict, Verdict::NoWin);
+
+  let vct = solver::solve(&b, Color::Black, RuleSet::Freestyle, Kind::Vct, Limits::for_kind(Kind::Vct));
+  assert_eq!(vct.verdict, Verdict::Win);
+  assert_wins(b.clone(), Color::Black, RuleSet::Freestyle, Kind::Vct, &vct.line);
+
+  // Under Renju the double three is forbidden for Black, so no winning line may use it.
+  let limits = Limits {
+    max_depth: 4,
+    max_nodes: 2_000,
+    ..Limits::for_kind(Kind::Vcf)
+  };
+  let renju = solver::solve(&b, Color::Black, RuleSet::Renju, Kind::Vct, limits);
+  if renju.verdict == Verdict::Win {
+    assert_ne!(at(&renju.line, 0), (7, 9));
+    assert_wins(b.clone(), Color::Black, RuleSet::Renju, Kind::Vct, &renju.line);
+  }
+}
+
+#[test]
+fn renju_black_cannot_block_on_a_forbidden_point() {
+  // (7, 7) is a double three for Black and also where White's diagonal four would end.
+  let b = board(&[(7, 5), (7, 6), (5, 7), (6, 7), (2, 2)], &[(3, 3), (4, 4), (5, 5), (0, 14), (14, 0)]);
+  assert!(rules::renju_forbidden(&b, 7, 7).is_some());
+
+  let s = solver::solve(&b, Color::White, RuleSet::Renju, Kind::Vcf, Limits::for_kind(Kind::Vcf));
+  assert_eq!(s.verdict, Verdict::Win);
+  assert_eq!(s.line.len(), 1);
+  assert_eq!(at(&s.line, 0), (6, 6));
+
+  let s = solver::solve(&b, Color::White, RuleSet::Freestyle, Kind::Vcf, Limits::for_kind(Kind::Vcf));
+  assert_ne!(s.verdict, Verdict::Win);
+}
+
+#[test]
+fn respects_limits() {
+  let b = board(&[(7, 7), (7, 8), (8, 9), (9, 9)], &[(0, 0), (0, 14), (14, 0), (14, 14)]);
+  let tiny = Limits {
+    max_depth: 6,
+    max_nodes: 1,
+    ..Limits::for_kind(Kind::Vct)
+  };
+  let s = solver::solve(&b, Color::Black, RuleSet::Freestyle, Kind::Vct, tiny);
+  assert_eq!(s.verdict, Verdict::Unknown);
+  assert!(s.line.is_empty());
+
+  let shallow = Limits {
+    max_depth: 0,
+    max_nodes: 1000,
+    ..Limits::for_kind(Kind::Vct)
+  };
+  let s = solver::solve(&b, Color::Black, RuleSet::Freestyle, Kind::Vct, shallow);
+  assert_eq!(s.verdict, Verdict::NoWin);
+
+  let out_of_time = Limits {
+    max_time: Duration::ZERO,
+    ..Limits::for_kind(Kind::Vct)
+  };
+  let s = solver::solve(&b, Color::Black, RuleSet::Freestyle, Kind::Vct, out_of_time);
+  assert_eq!(s.verdict, Verdict::Unknown);
+}
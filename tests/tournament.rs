@@ -0,0 +1,55 @@
+use server::ai::Difficulty;
+use server::bots::Builtin;
+use server::rooms::{Color, RoomOptions, RoomService};
+use server::tournament::{self, Crosstable, Entrant, Format, Pairing, Tally};
+
+#[test]
+fn schedules_alternate_colors() {
+  let games = tournament::schedule(Format::RoundRobin, 3, 2);
+  assert_eq!(games.len(), 6);
+  assert_eq!(games[0], Pairing { black: 0, white: 1 });
+  assert_eq!(games[3], Pairing { black: 1, white: 0 });
+
+  let games = tournament::schedule(Format::Gauntlet, 4, 1);
+  assert!(games.iter().all(|g| g.black == 0));
+  assert_eq!(games.len(), 3);
+}
+
+#[test]
+fn crosstable_and_elo() {
+  let mut table = Crosstable::new(vec!["a".to_string(), "b".to_string()]);
+  table.record(0, 1, Some(Color::Black));
+  table.record(1, 0, Some(Color::Black));
+  table.record(0, 1, None);
+  table.record(1, 0, Some(Color::White));
+  assert_eq!(table.head_to_head(0, 1), Tally { wins: 2, draws: 1, losses: 1 });
+  assert_eq!(table.total(1).points(), 1.5);
+
+  let even = tournament::estimate_elo(Tally { wins: 5, draws: 0, losses: 5 }).unwrap();
+  assert_eq!(even.elo, 0.0);
+  assert!(even.error > 100.0);
+  let strong = tournament::estimate_elo(Tally { wins: 75, draws: 0, losses: 25 }).unwrap();
+  assert!((strong.elo - 190.8).abs() < 0.1);
+  assert!(strong.error < even.error);
+  assert_eq!(tournament::estimate_elo(Tally::default()), None);
+}
+
+#[tokio::test]
+async fn plays_a_game_and_writes_notation() {
+  let (sink, mut finished) = tokio::sync::mpsc::unbounded_channel();
+  let rooms = RoomService::with_history(sink);
+  let mut a = Entrant { name: "a".to_string(), engine: Box::new(Builtin(Difficulty::Easy)) };
+  let mut b = Entrant { name: "b".to_string(), engine: Box::new(Builtin(Difficulty::Easy)) };
+  let options = RoomOptions { board_size: 9, ..Default::default() };
+
+  let game = tournament::play_game(&rooms, &mut finished, &options, &mut a, &mut b).await.unwrap();
+  assert_eq!((game.black.as_str(), game.white.as_str()), ("a", "b"));
+  assert!(!game.moves.is_empty());
+  assert_eq!(rooms.room_id_for_user("a"), None);
+
+  let pgn = tournament::game_notation(1, &game);
+  assert!(pgn.contains("[Black \"a\"]"));
+  // The first stone goes in the middle of the 9x9 board.
+  assert!(pgn.contains("1. e5 "));
+  assert_eq!(tournament::coord_name(15, 7, 7), "h8");
+}